@@ -0,0 +1,541 @@
+use crate::error::*;
+use crate::params::*;
+use crate::wormod::generate;
+use crate::wormod::generate::Charset;
+use crate::wormod::generate::Mask;
+
+use clap::Args;
+use clap::CommandFactory;
+use clap::FromArgMatches;
+use clap::Subcommand;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Wordlists manipulation
+///
+/// WorMod manipulates, generates, analyzes, combines and checks wordlists.
+/// Each task is performed by its own command, see the help of each of them
+/// for further details.
+/// When no command is given, the options are those of the transform command,
+/// so that for example 'wormod --sort' is the same as 'wormod transform
+/// --sort'.
+#[derive(Parser)]
+#[command(about, version, subcommand_required=true, arg_required_else_help=true)]
+pub(crate) struct Cli {
+    /// The task to perform
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// The tasks which can be performed
+#[derive(Subcommand)]
+pub enum Command {
+    Transform(Box<Params>),
+    Generate(GenerateParams),
+    Stats(StatsParams),
+    Merge(MergeParams),
+    Check(CheckParams),
+}
+
+/// The options about how input wordlists are read
+#[derive(Args)]
+pub struct InputOptions {
+    /// How to handle input lines containing invalid UTF-8 sequences
+    ///
+    /// See the help of the transform command for further details.
+    #[arg(long, value_name="POLICY", value_enum, default_value_t=InvalidUtf8Policy::Fail)]
+    pub invalid_utf8: InvalidUtf8Policy,
+    /// The character encoding of the input wordlists
+    #[arg(long, value_name="ENCODING", value_enum, default_value_t=TextEncoding::Utf8)]
+    pub input_encoding: TextEncoding,
+    /// Treat entries as raw sequences of bytes instead of UTF-8 text
+    ///
+    /// The input is never decoded, thus --invalid-utf8 has no effect.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub bytes: bool,
+    /// Decode input lines written in hashcat's $HEX[...] notation
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub decode_hex: bool,
+    /// The maximum amount of memory WorMod is allowed to use
+    ///
+    /// See the help of the transform command for further details.
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    pub memory_limit: Option<usize>,
+}
+
+/// The options about how the output wordlist is written
+#[derive(Args)]
+pub struct OutputOptions {
+    /// The path of the output wordlist
+    ///
+    /// Omit to write to stdout.
+    #[arg(short, long, value_name="FILE")]
+    pub output: Option<PathBuf>,
+    /// Compress the output with the given format
+    ///
+    /// Omit to infer the format from the extension of the output file
+    /// (.gz, .bz2, .xz, .zst), or to not compress when writing to stdout.
+    #[arg(long, value_name="FORMAT", value_enum)]
+    pub compress: Option<CompressionFormat>,
+    /// Append to the output file instead of overwriting
+    ///
+    /// Can only be used in conjunction with --output.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub append_output: bool,
+    /// The character encoding of the output wordlist
+    #[arg(long, value_name="ENCODING", value_enum, default_value_t=TextEncoding::Utf8)]
+    pub output_encoding: TextEncoding,
+    /// How to handle entries not representable in the output encoding
+    #[arg(long, value_name="POLICY", value_enum, default_value_t=UnrepresentablePolicy::Skip)]
+    pub unrepresentable: UnrepresentablePolicy,
+    /// Encode output entries in hashcat's $HEX[...] notation when needed
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub encode_hex: bool,
+}
+
+/// Generate a wordlist from a mask
+///
+/// Every candidate described by the mask is written to the output, one per
+/// line. The number of candidates is the product of the sizes of the charsets
+/// of each position of the mask, thus it grows really fast with the length
+/// of the mask.
+#[derive(Args)]
+pub struct GenerateParams {
+    /// The mask describing the candidates
+    ///
+    /// Each position of the mask is either a literal character or one of the
+    /// following placeholders: ?l for lowercase letters, ?u for uppercase
+    /// letters, ?d for digits, ?h and ?H for lowercase and uppercase
+    /// hexadecimal digits, ?s for symbols and space, ?a for all of them, ?1
+    /// to ?9 for the charsets given with --charset, and ?? for a literal '?'.
+    /// Candidates are generated in order, the last position changing first.
+    #[arg(short, long, value_name="MASK", value_parser=Mask::parse)]
+    pub mask: Mask,
+    /// A custom charset, to be referenced in the mask
+    ///
+    /// Can be used multiple times: the first charset is referenced as ?1, the
+    /// second one as ?2 and so on. Charsets can contain the placeholders
+    /// of the built-in charsets, as in '?l?d_'.
+    #[arg(short, long, value_name="CHARS", value_parser=generate::parse_charset)]
+    pub charset: Vec<Charset>,
+    #[command(flatten)]
+    pub output: OutputOptions,
+    /// Do not follow symlinks in the output path
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub no_follow_symlinks: bool,
+}
+
+/// Report statistics about a wordlist
+///
+/// The whole input is read once, then the number of entries, the length of
+/// the entries and the classes of characters they are made of are written
+/// to the standard output.
+#[derive(Args)]
+pub struct StatsParams {
+    /// The path of the input wordlist
+    ///
+    /// Omit to read from stdin.
+    #[arg(short, long, value_name="FILE")]
+    pub input: Option<PathBuf>,
+    #[command(flatten)]
+    pub input_options: InputOptions,
+    /// The unit in which the length of the entries is measured
+    ///
+    /// Can only be bytes when using --bytes.
+    #[arg(long, value_name="UNIT", value_enum, default_value_t=LengthUnit::Bytes)]
+    pub length_unit: LengthUnit,
+    /// Do not count the unique entries
+    ///
+    /// Counting them requires to keep all the distinct entries in memory,
+    /// and the process will exit-fail in case the system will become close
+    /// to run out of memory.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub skip_unique: bool,
+    /// Do not follow symlinks in the input path
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub no_follow_symlinks: bool,
+}
+
+/// Combine multiple wordlists into one
+///
+/// The resulting wordlist only contains unique entries. This is the same as
+/// using --set-operation with the transform command, without any further
+/// manipulation.
+#[derive(Args)]
+pub struct MergeParams {
+    /// The path of an input wordlist
+    ///
+    /// Shall be used at least twice.
+    #[arg(short, long, value_name="FILE", required=true)]
+    pub input: Vec<PathBuf>,
+    /// How to combine the input wordlists
+    ///
+    /// The symmetric difference keeps the entries contained in exactly one
+    /// of the inputs, while the difference keeps the entries of the first
+    /// input that are not contained in any of the others.
+    #[arg(long, value_name="OPERATION", value_enum, default_value_t=SetOperation::Union)]
+    pub operation: SetOperation,
+    /// Assume that all the input wordlists are already sorted
+    ///
+    /// Allows to merge the inputs while reading them, instead of storing all
+    /// of them in memory. The resulting wordlist is sorted.
+    /// The process will exit-fail if any of the inputs turns out not to be
+    /// sorted.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub sorted_inputs: bool,
+    /// Keep reading and flushing instead of waiting for the EOF
+    ///
+    /// Can only be used in conjunction with --sorted-inputs.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub pipe: bool,
+    #[command(flatten)]
+    pub input_options: InputOptions,
+    #[command(flatten)]
+    pub output: OutputOptions,
+    /// Do not follow symlinks in input/output paths
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub no_follow_symlinks: bool,
+}
+
+/// Check that a wordlist has the requested properties
+///
+/// The whole input is read once, and the process exit-fails at the first
+/// entry not passing the checks. Lines which cannot be decoded are handled
+/// according to --invalid-utf8, thus by default the input is also checked
+/// to be valid UTF-8.
+#[derive(Args)]
+pub struct CheckParams {
+    /// The path of the input wordlist
+    ///
+    /// Omit to read from stdin.
+    #[arg(short, long, value_name="FILE")]
+    pub input: Option<PathBuf>,
+    #[command(flatten)]
+    pub input_options: InputOptions,
+    /// Check that the entries are sorted
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub sorted: bool,
+    /// Check that every entry appears only once
+    ///
+    /// Unless used in conjunction with --sorted, all the distinct entries are
+    /// kept in memory, and the process will exit-fail in case the system will
+    /// become close to run out of memory.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub unique: bool,
+    /// Check that no entry is shorter than the given length
+    #[arg(long, value_name="N", action=clap::ArgAction::Set)]
+    pub min_len: Option<usize>,
+    /// Check that no entry is longer than the given length
+    #[arg(long, value_name="N", action=clap::ArgAction::Set)]
+    pub max_len: Option<usize>,
+    /// The unit in which the length of the entries is measured
+    ///
+    /// Can only be bytes when using --bytes.
+    #[arg(long, value_name="UNIT", value_enum, default_value_t=LengthUnit::Bytes)]
+    pub length_unit: LengthUnit,
+    /// Do not follow symlinks in the input path
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub no_follow_symlinks: bool,
+}
+
+impl Command {
+    /// Parses the command line arguments
+    ///
+    /// Prints the help or the version if requested, and calls terminate
+    /// with a failure code if the arguments are not valid.
+    /// Arguments not preceded by a command are those of transform
+    pub fn from_command_line() -> Result<Self, WormodError> {
+        Self::parse_args(std::env::args_os())
+            .unwrap_or_else(|e| e.exit())
+            .with_pipeline_file()
+    }
+
+    /// Parses the given arguments, the first one being the program name
+    ///
+    /// Arguments not preceded by a command are those of transform
+    pub fn from_args<I, T>(args: I) -> Result<Self, WormodError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        Self::parse_args(args)
+            .map_err(invalid_arguments)?
+            .with_pipeline_file()
+    }
+
+    /// Checks the options of the command to ensure they are consistent
+    pub fn validate(self) -> Result<Self, WormodError> {
+        match self {
+            Command::Transform(params) => params.validate().map(Box::new).map(Command::Transform),
+            Command::Generate(params) => params.validate().map(Command::Generate),
+            Command::Stats(params) => params.validate().map(Command::Stats),
+            Command::Merge(params) => params.validate().map(Command::Merge),
+            Command::Check(params) => params.validate().map(Command::Check),
+        }
+    }
+
+    /// Replaces the parameters of transform with those of the pipeline
+    /// file, if any
+    fn with_pipeline_file(self) -> Result<Self, WormodError> {
+        match self {
+            Command::Transform(params) => params.with_pipeline_file().map(Box::new).map(Command::Transform),
+            command => Ok(command),
+        }
+    }
+
+    /// Parses the arguments
+    ///
+    /// The arguments of transform are parsed on their own in order to
+    /// record the order of the operations
+    fn parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Cli::command().try_get_matches_from(with_default_command(args))?;
+        match matches.subcommand() {
+            Some(("transform", sub_matches)) => Params::from_matches(sub_matches).map(Box::new).map(Command::Transform),
+            _ => Cli::from_arg_matches(&matches).map(|cli| cli.command),
+        }
+    }
+}
+
+/// Inserts the transform command if the arguments do not start with one
+///
+/// Keeps the arguments of the versions without commands working
+fn with_default_command<I, T>(args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let mut args : Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let cli = Cli::command();
+    let is_command = |arg: &str| {
+        cli.find_subcommand(arg).is_some()
+            || matches!(arg, "help" | "-h" | "--help" | "-V" | "--version")
+    };
+    if args.get(1).is_some_and(|arg| !arg.to_str().is_some_and(is_command)) {
+        args.insert(1, OsString::from("transform"));
+    }
+    args
+}
+
+impl InputOptions {
+    /// Sets the options in the given parameters
+    fn apply(&self, params: &mut Params) {
+        params.invalid_utf8 = self.invalid_utf8;
+        params.input_encoding = self.input_encoding;
+        params.bytes = self.bytes;
+        params.decode_hex = self.decode_hex;
+        params.memory_limit = self.memory_limit;
+    }
+}
+
+impl OutputOptions {
+    /// Sets the options in the given parameters
+    fn apply(&self, params: &mut Params) {
+        params.output = self.output.clone();
+        params.compress = self.compress;
+        params.append_output = self.append_output;
+        params.output_encoding = self.output_encoding;
+        params.unrepresentable = self.unrepresentable;
+        params.encode_hex = self.encode_hex;
+    }
+
+    /// Checks the options to ensure they are consistent
+    fn validate(&self) -> Result<(), WormodError> {
+        if self.output.is_none() && self.append_output {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --append-output"),
+                ("Cannot append to a file without an output file")
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl GenerateParams {
+    /// Checks the options to ensure they are consistent
+    pub fn validate(mut self) -> Result<Self, WormodError> {
+        let charsets = self.mask.resolve(&self.charset)?;
+        if generate::n_candidates(&charsets).is_none() {
+            return Err(error!(
+                InvalidOptions,
+                ("Too many candidates described by the mask"),
+                ("The number of candidates exceeds 2^64")
+            ));
+        }
+        self.output.validate()?;
+        let mut params = self.io_params();
+        params.validate_paths()?;
+        self.output.output = params.output;
+        Ok(self)
+    }
+
+    /// The parameters of the output
+    pub(crate) fn io_params(&self) -> Params {
+        let mut params = Params::defaults();
+        self.output.apply(&mut params);
+        params.no_follow_symlinks = self.no_follow_symlinks;
+        params
+    }
+}
+
+impl StatsParams {
+    /// Checks the options to ensure they are consistent
+    pub fn validate(mut self) -> Result<Self, WormodError> {
+        if self.input_options.bytes && self.length_unit != LengthUnit::Bytes {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --bytes --length-unit"),
+                ("Raw entries can only be measured in bytes")
+            ));
+        }
+        let mut params = self.io_params();
+        params.validate_paths()?;
+        self.input = params.input.pop();
+        Ok(self)
+    }
+
+    /// The parameters of the input
+    pub(crate) fn io_params(&self) -> Params {
+        let mut params = Params::defaults();
+        params.input = self.input.iter().cloned().collect();
+        self.input_options.apply(&mut params);
+        params.no_follow_symlinks = self.no_follow_symlinks;
+        params
+    }
+}
+
+impl MergeParams {
+    /// Checks the options to ensure they are consistent
+    pub fn validate(mut self) -> Result<Self, WormodError> {
+        if self.input.len() < 2 {
+            return Err(error!(
+                InvalidOptions,
+                ("Not enough input wordlists: {}", self.input.len()),
+                ("Cannot combine less than two input wordlists")
+            ));
+        } else if self.pipe && !self.sorted_inputs {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --pipe"),
+                ("Cannot combine unsorted inputs in pipe mode, see --sorted-inputs")
+            ));
+        }
+        let params = self.io_params().validate()?;
+        self.input = params.input;
+        self.output.output = params.output;
+        Ok(self)
+    }
+
+    /// The parameters of transform performing the same task
+    pub(crate) fn io_params(&self) -> Params {
+        let mut params = Params::defaults();
+        params.input = self.input.clone();
+        params.set_operation = Some(self.operation);
+        params.sorted_inputs = self.sorted_inputs;
+        params.pipe = self.pipe;
+        self.input_options.apply(&mut params);
+        self.output.apply(&mut params);
+        params.no_follow_symlinks = self.no_follow_symlinks;
+        params
+    }
+}
+
+impl CheckParams {
+    /// Checks the options to ensure they are consistent
+    pub fn validate(mut self) -> Result<Self, WormodError> {
+        if self.min_len.zip(self.max_len).is_some_and(|(min, max)| max < min) {
+            return Err(error!(
+                InvalidOptions,
+                ("Invalid min-max length values: {}-{}", self.min_len.unwrap(), self.max_len.unwrap()),
+                ("Maximum length cannot be smaller than minimum length")
+            ));
+        } else if self.input_options.bytes && self.length_unit != LengthUnit::Bytes {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --bytes --length-unit"),
+                ("Raw entries can only be measured in bytes")
+            ));
+        }
+        let mut params = self.io_params();
+        params.validate_paths()?;
+        self.input = params.input.pop();
+        Ok(self)
+    }
+
+    /// The parameters of the input
+    pub(crate) fn io_params(&self) -> Params {
+        let mut params = Params::defaults();
+        params.input = self.input.iter().cloned().collect();
+        self.input_options.apply(&mut params);
+        params.no_follow_symlinks = self.no_follow_symlinks;
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validated(args: &[&str]) -> Result<Command, WormodError> {
+        Command::from_args(std::iter::once("wormod").chain(args.iter().copied()))?.validate()
+    }
+
+    #[test]
+    fn inserts_default_command() {
+        let with_default = |args: &[&str]| -> Vec<String> {
+            with_default_command(args.iter().copied())
+                .into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect()
+        };
+        assert_eq!(with_default(&["wormod"]), ["wormod"]);
+        assert_eq!(with_default(&["wormod", "--sort"]), ["wormod", "transform", "--sort"]);
+        assert_eq!(with_default(&["wormod", "stats"]), ["wormod", "stats"]);
+        assert_eq!(with_default(&["wormod", "transform", "-i", "a"]), ["wormod", "transform", "-i", "a"]);
+        assert_eq!(with_default(&["wormod", "--help"]), ["wormod", "--help"]);
+        assert_eq!(with_default(&["wormod", "help", "check"]), ["wormod", "help", "check"]);
+        assert!(matches!(validated(&["--reverse"]), Ok(Command::Transform(_))));
+    }
+
+    #[test]
+    fn validates_generate() {
+        assert!(matches!(validated(&["generate", "-m", "?a?a?a?a?a?a?a?a?a"]), Ok(Command::Generate(_))));
+        assert!(matches!(validated(&["generate", "-m", "?1?d", "-c", "ab"]), Ok(Command::Generate(_))));
+        assert!(matches!(
+            validated(&["generate", "-m", "?a?a?a?a?a?a?a?a?a?a"]),
+            Err(WormodError::InvalidOptions(_))
+        ));
+        assert!(matches!(validated(&["generate", "-m", "?1"]), Err(WormodError::InvalidOptions(_))));
+        assert!(matches!(
+            validated(&["generate", "-m", "?d", "--append-output"]),
+            Err(WormodError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn validates_stats() {
+        assert!(matches!(validated(&["stats", "--length-unit", "chars"]), Ok(Command::Stats(_))));
+        assert!(matches!(validated(&["stats", "--bytes"]), Ok(Command::Stats(_))));
+        assert!(matches!(
+            validated(&["stats", "--bytes", "--length-unit", "graphemes"]),
+            Err(WormodError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn validates_check() {
+        assert!(matches!(validated(&["check", "--min-len", "2", "--max-len", "2"]), Ok(Command::Check(_))));
+        assert!(matches!(
+            validated(&["check", "--min-len", "3", "--max-len", "2"]),
+            Err(WormodError::InvalidOptions(_))
+        ));
+        assert!(matches!(
+            validated(&["check", "--bytes", "--length-unit", "chars"]),
+            Err(WormodError::InvalidOptions(_))
+        ));
+    }
+}
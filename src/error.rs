@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// The errors which can occur while manipulating wordlists
+#[derive(Debug)]
+pub enum WormodError {
+    /// The options are not consistent
+    InvalidOptions(Message),
+    /// An input or output path is not valid
+    Path(Message),
+    /// Reading or writing failed
+    Io(Message),
+    /// Not enough memory to complete the operations
+    Memory(Message),
+    /// The input cannot be decoded, or the output cannot be encoded
+    Encoding(Message),
+    /// The rules cannot be loaded
+    Rules(Message),
+    /// The wordlist did not pass the requested checks
+    Check(Message),
+}
+
+/// The description of an error
+#[derive(Debug)]
+pub struct Message {
+    /// What went wrong
+    pub summary: String,
+    /// Further details about the failure, if any
+    pub details: Vec<String>,
+}
+
+impl WormodError {
+    /// The description of the error
+    pub fn message(&self) -> &Message {
+        match self {
+            WormodError::InvalidOptions(m)
+            | WormodError::Path(m)
+            | WormodError::Io(m)
+            | WormodError::Memory(m)
+            | WormodError::Encoding(m)
+            | WormodError::Rules(m)
+            | WormodError::Check(m) => m,
+        }
+    }
+
+    /// The exit code of the command line tool for this kind of error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            WormodError::InvalidOptions(_) => 2,
+            WormodError::Path(_) => 3,
+            WormodError::Io(_) => 4,
+            WormodError::Memory(_) => 5,
+            WormodError::Encoding(_) => 6,
+            WormodError::Rules(_) => 7,
+            WormodError::Check(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for WormodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = self.message();
+        write!(f, "{}", message.summary)?;
+        for detail in message.details.iter() {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WormodError {}
+
+/// Builds an error of the given kind, with the same syntax as `print_info`
+macro_rules! error {
+    ($kind:ident, ($($s:expr),+) $(, ($($d:expr),+))*) => {
+        $crate::error::WormodError::$kind($crate::error::Message {
+            summary: format!($($s),+),
+            details: vec![$(format!($($d),+)),*],
+        })
+    };
+}
+
+pub(crate) use error;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        let errors = [
+            error!(InvalidOptions, ("options")),
+            error!(Path, ("path")),
+            error!(Io, ("io")),
+            error!(Memory, ("memory")),
+            error!(Encoding, ("encoding")),
+            error!(Rules, ("rules")),
+            error!(Check, ("check")),
+        ];
+        let codes : Vec<i32> = errors.iter().map(WormodError::exit_code).collect();
+        assert_eq!(codes, [2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn displays_details() {
+        let e = error!(Path, ("Invalid path: {:?}", "a"), ("Not a file"), ("{} links", 2));
+        assert_eq!(e.to_string(), "Invalid path: \"a\": Not a file: 2 links");
+        assert_eq!(e.message().details.len(), 2);
+    }
+}
@@ -0,0 +1,57 @@
+//! Wordlists manipulation
+//!
+//! WorMod takes a wordlist as input, manipulates it by applying the requested
+//! operations and outputs the modified version.
+//!
+//! Each task of the command line tool is described by a [`Command`] and
+//! performed by [`run_command`]. The manipulation of a wordlist, as performed
+//! by the transform command, is run by [`run`] given the [`Params`]. Its
+//! steps are also available on their own: the input is read with the
+//! [`reader`], entries are manipulated by the [`Pipeline`] of operations,
+//! either all at once or one at a time, and finally written with the
+//! [`writer`]. Failures are returned as a [`WormodError`].
+
+mod cli;
+mod error;
+mod params;
+mod pipeline_file;
+mod print;
+mod wormod;
+
+pub use cli::CheckParams;
+pub use cli::Command;
+pub use cli::GenerateParams;
+pub use cli::InputOptions;
+pub use cli::MergeParams;
+pub use cli::OutputOptions;
+pub use cli::StatsParams;
+pub use error::Message;
+pub use error::WormodError;
+pub use params::CompressionFormat;
+pub use params::HashAlgorithm;
+pub use params::InvalidUtf8Policy;
+pub use params::LengthUnit;
+pub use params::MatchMode;
+pub use params::OperationKind;
+pub use params::Params;
+pub use params::RulesSyntax;
+pub use params::SetOperation;
+pub use params::TextEncoding;
+pub use params::UnrepresentablePolicy;
+pub use print::print_error;
+pub use wormod::run;
+pub use wormod::run_command;
+pub use wormod::TrackingAllocator;
+pub use wormod::decoder;
+pub use wormod::entry;
+pub use wormod::generate;
+pub use wormod::pipeline;
+pub use wormod::reader;
+pub use wormod::rules;
+pub use wormod::wordlist;
+pub use wormod::writer;
+pub use wormod::entry::Entry;
+pub use wormod::pipeline::Operation;
+pub use wormod::pipeline::Pipeline;
+pub use wormod::pipeline::StatelessOperation;
+pub use wormod::wordlist::Wordlist;
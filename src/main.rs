@@ -1,12 +1,13 @@
-mod params;
-mod print;
-mod wormod;
+use wormod::Command;
+use wormod::TrackingAllocator;
 
-use params::*;
-use wormod::run;
+/// Keeps track of the allocated memory, see --memory-limit
+#[global_allocator]
+static ALLOCATOR : TrackingAllocator = TrackingAllocator;
 
 fn main() {
-    if let Err(e) = run(Params::parse().validate()) {
-        exit_err!(("{}", e.to_string()));
+    if let Err(e) = Command::from_command_line().and_then(Command::validate).and_then(wormod::run_command) {
+        wormod::print_error(&e);
+        std::process::exit(e.exit_code());
     }
 }
@@ -1,21 +1,32 @@
-use crate::print::*;
+use crate::error::*;
 
+use regex::Regex;
+use std::ffi::OsString;
+use std::path::Path;
 use std::path::PathBuf;
 
 pub(crate) use clap::Parser;
+use clap::CommandFactory;
+use clap::ArgMatches;
+use clap::FromArgMatches;
+use clap::parser::ValueSource;
 
-/// Wordlists manipulation
+/// Manipulate a wordlist
 ///
 /// WorMod takes a wordlist as input, manipulates it by applying the requested
 /// operations and outputs the modified version.
 /// The input wordlist shall contain one entry per line and will be treated as
 /// if it does, which means that if one line contains multiple entries they
 /// will be treated as if they were only one.
-/// The input wordlist shall also contain only valid Unicode characters and the
-/// process will exit-fail if it does not.
+/// The input wordlist shall also contain only valid UTF-8 characters and the
+/// process will exit-fail if it does not, unless a different policy is set
+/// with --invalid-utf8.
+/// Operations are applied in the same order as they are given, so that for
+/// example '--reverse --sort' sorts the reversed entries, while '--sort
+/// --reverse' reverses the sorted entries.
 #[derive(Parser)]
 #[command(about, version)]
-pub(crate) struct Params {
+pub struct Params {
     /// The path of the input wordlist
     ///
     /// Omit to read from stdin. In such a case, if not running in pipe mode,
@@ -23,117 +34,579 @@ pub(crate) struct Params {
     /// is reached. Consider using --pipe if the input size is unknown: the
     /// process will eventually exit-fail in case the system will become close
     /// to run out of memory.
+    /// Can be used multiple times in conjunction with --set-operation.
     #[arg(short, long, value_name="FILE")]
-    pub(crate) input: Option<PathBuf>,
+    pub input: Vec<PathBuf>,
     /// The path of the output wordlist
     ///
     /// Omit to write to stdout.
     #[arg(short, long, value_name="FILE")]
-    pub(crate) output: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    /// Compress the output with the given format
+    ///
+    /// Omit to infer the format from the extension of the output file
+    /// (.gz, .bz2, .xz, .zst), or to not compress when writing to stdout.
+    /// Compressed inputs are always detected and decompressed, regardless
+    /// of this option.
+    #[arg(long, value_name="FORMAT", value_enum)]
+    pub compress: Option<CompressionFormat>,
     /// Append to the output file instead of overwriting
     ///
     /// Can only be used in conjunction with --output.
     #[arg(long, action=clap::ArgAction::SetTrue)]
-    pub(crate) append_output: bool,
+    pub append_output: bool,
+    /// How to handle input lines containing invalid UTF-8 sequences
+    ///
+    /// Lines can be skipped, decoded replacing the invalid sequences with the
+    /// U+FFFD replacement character, encoded in the hashcat $HEX[...] format,
+    /// or make the process exit-fail. Unless failing, the number of affected
+    /// lines is reported once the EOF is reached. Lines to be encoded are
+    /// manipulated byte by byte, like with --bytes, and only encoded once
+    /// written.
+    #[arg(long, value_name="POLICY", value_enum, default_value_t=InvalidUtf8Policy::Fail)]
+    pub invalid_utf8: InvalidUtf8Policy,
+    /// The character encoding of the input wordlists
+    ///
+    /// Inputs are converted to UTF-8 before any manipulation. A byte order
+    /// mark at the beginning of the input is removed and takes precedence
+    /// over the given encoding, thus UTF-16 inputs are detected and read with
+    /// the right endianness whatever the encoding. Sequences which cannot be
+    /// decoded are handled like invalid UTF-8 ones, see --invalid-utf8.
+    /// Unless an encoding is given, inputs are read as they are when using
+    /// --bytes.
+    #[arg(long, value_name="ENCODING", value_enum, default_value_t=TextEncoding::Utf8)]
+    pub input_encoding: TextEncoding,
+    /// The character encoding of the output wordlist
+    ///
+    /// Entries are converted from UTF-8 right before being written. See
+    /// --unrepresentable for entries which cannot be converted.
+    #[arg(long, value_name="ENCODING", value_enum, default_value_t=TextEncoding::Utf8)]
+    pub output_encoding: TextEncoding,
+    /// How to handle entries not representable in the output encoding
+    ///
+    /// Entries containing characters which do not exist in the output encoding
+    /// can be skipped, have those characters replaced with '?', be written as
+    /// the $HEX[...] of their UTF-8 bytes or make the process exit-fail.
+    /// Unless failing, the number of affected entries is reported at the end.
+    #[arg(long, value_name="POLICY", value_enum, default_value_t=UnrepresentablePolicy::Skip)]
+    pub unrepresentable: UnrepresentablePolicy,
+    /// Treat entries as raw sequences of bytes instead of UTF-8 text
+    ///
+    /// The input is never decoded, thus --invalid-utf8 has no effect.
+    /// Entries are compared, sorted and deduplicated byte by byte and their
+    /// length is measured in bytes. Regular expressions are matched against
+    /// the raw bytes without Unicode support, so that '.' and escape sequences
+    /// like '\xE9' match single bytes. Reversing an entry reverses its bytes,
+    /// and rules only change the case of ASCII letters.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub bytes: bool,
+    /// Decode input lines written in hashcat's $HEX[...] notation
+    ///
+    /// Such lines are turned into their real bytes before any manipulation.
+    /// Lines which are not valid hexadecimal notation are left untouched,
+    /// as well as lines which would decode to line breaks. Unless using
+    /// --bytes, decoded lines which are not valid UTF-8 are handled according
+    /// to --invalid-utf8.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub decode_hex: bool,
+    /// Encode output entries in hashcat's $HEX[...] notation when needed
+    ///
+    /// Entries containing non-printable or non-ASCII characters, as well as
+    /// colons or entries which already look like $HEX[...], get encoded so
+    /// that hashcat reads them back exactly as they are.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub encode_hex: bool,
     /// Do not follow symlinks in input/output paths
     #[arg(long, action=clap::ArgAction::SetTrue)]
-    pub(crate) no_follow_symlinks: bool,
+    pub no_follow_symlinks: bool,
     /// Keep reading and flushing instead of waiting for the EOF
     ///
     /// Reccomended when the input is stdin and the output is stdout, in order
     /// to reduce memory usage, or when the input size is unknown.
-    /// In pipe mode the input is read one line at a time, manipulated
-    /// on-the-fly and suddenly written to the output.
+    /// In pipe mode the input is read in batches of lines, which are
+    /// manipulated on-the-fly and written to the output as soon as the batch
+    /// is complete, in the same order as the input. See --max-latency for
+    /// how long a batch may wait for more lines.
     /// Some operations are not available or may have downsides when running
     /// in pipe mode. See --sort and --unique for further details.
     #[arg(long, action=clap::ArgAction::SetTrue)]
-    pub(crate) pipe: bool,
+    pub pipe: bool,
+    /// The maximum time in milliseconds a line may wait for the rest of its
+    /// batch before being manipulated and written, when running in pipe mode
+    ///
+    /// Batches are completed earlier when enough lines have been read.
+    /// Lower values let interactive pipelines see the output promptly, while
+    /// higher values allow bigger batches, which are manipulated faster when
+    /// using --threads.
+    #[arg(long, value_name="MS", default_value_t=100)]
+    pub max_latency: u64,
+    /// Combine multiple input wordlists into one
+    ///
+    /// Requires at least two --input wordlists. The resulting wordlist only
+    /// contains unique entries, which are then manipulated by the other
+    /// operations as if they came from a single input.
+    /// The symmetric difference keeps the entries contained in exactly one
+    /// of the inputs, while the difference keeps the entries of the first
+    /// input that are not contained in any of the others.
+    #[arg(long, value_name="OPERATION", value_enum)]
+    pub set_operation: Option<SetOperation>,
+    /// Assume that all the input wordlists are already sorted
+    ///
+    /// Allows --set-operation to merge the inputs while reading them, instead
+    /// of storing all of them in memory. The resulting wordlist is sorted.
+    /// The process will exit-fail if any of the inputs turns out not to be
+    /// sorted. Required when using --set-operation with --pipe.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub sorted_inputs: bool,
     /// Sort the wordlist
     ///
-    /// Cannot be used in conjunction with --pipe.
+    /// When used in conjunction with --pipe, or when the input file is too big
+    /// to be stored in memory, the entries are sorted in chunks that are
+    /// temporarily written to disk and merged once the EOF is reached. In such
+    /// a case, nothing is written to the output before the EOF. See --tmp-dir
+    /// for the location of the temporary files.
     #[arg(long, action=clap::ArgAction::SetTrue)]
-    pub(crate) sort: bool,
+    pub sort: bool,
     /// Remove duplicates from the wordlist
     ///
-    /// When used in conjunction with --pipe, in order to provide only unique
-    /// entries an internal list of all the past entries will be kept. Memory
+    /// When used in conjunction with --sort, duplicates are removed while
+    /// sorting, with no additional memory usage.
+    /// When used in conjunction with --pipe alone, in order to provide only unique
+    /// entries an internal set of all the past entries will be kept. Memory
     /// usage will hence increase accordingly and the process will eventually
-    /// exit-fail in case the system become close to run out of memory, not to
-    /// mention the performance overhead of re-checking the entire list at each
-    /// iteration.
+    /// exit-fail in case the system become close to run out of memory.
+    /// When used without --sort, the first occurrence of each entry is kept
+    /// and the original order of the entries is preserved. If the input file
+    /// is too big to be stored in memory, the entries are partitioned by their
+    /// hash into temporary files, from each of which duplicates are removed
+    /// in turn: the resulting entries are then grouped by partition, unless
+    /// using --keep-order. See --tmp-dir for the location of the temporary
+    /// files.
+    /// See --hasher for the hashing algorithm used to spot duplicates.
     #[arg(long, action=clap::ArgAction::SetTrue)]
-    pub(crate) unique: bool,
+    pub unique: bool,
+    /// Keep the last occurrence of each entry instead of the first one
+    ///
+    /// Can only be used in conjunction with --unique, and cannot be used in
+    /// conjunction with --sort or --pipe.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub keep_last: bool,
+    /// Restore the original order of the entries when removing duplicates
+    /// on disk
+    ///
+    /// The position of each entry is stored along with it in the temporary
+    /// files used by --unique, and the resulting entries are sorted back by
+    /// their position, on disk if needed, before being written.
+    /// Can only be used in conjunction with --unique, and cannot be used in
+    /// conjunction with --sort or --pipe.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub keep_order: bool,
+    /// Remove duplicates using a fixed amount of memory, at the cost of
+    /// occasionally discarding unique entries
+    ///
+    /// Can only be used in conjunction with --pipe.
+    /// Entries are recorded in a Bloom filter sized according to
+    /// --approx-capacity and --approx-error-rate. Once the EOF is reached,
+    /// the expected false-positive rate is reported.
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    pub unique_approx: bool,
+    /// The number of unique entries expected when using --unique-approx
+    ///
+    /// Exceeding it increases the false-positive rate beyond the requested one.
+    #[arg(long, value_name="N", default_value_t=100_000_000)]
+    pub approx_capacity: usize,
+    /// The maximum false-positive rate allowed when using --unique-approx
+    ///
+    /// The probability of a unique entry being discarded, given that the
+    /// number of unique entries does not exceed --approx-capacity.
+    #[arg(long, value_name="RATE", default_value_t=0.001)]
+    pub approx_error_rate: f64,
+    /// The hashing algorithm used to remove duplicates without sorting
+    ///
+    /// FNV-1a is faster, but an input crafted to produce collisions can
+    /// severely degrade performance.
+    #[arg(long, value_name="ALGORITHM", value_enum, default_value_t=HashAlgorithm::Siphash)]
+    pub hasher: HashAlgorithm,
+    /// The directory where to store temporary files
+    ///
+    /// Omit to use the default temporary directory of the system.
+    #[arg(long, value_name="DIR")]
+    pub tmp_dir: Option<PathBuf>,
+    /// The maximum amount of memory WorMod is allowed to use
+    ///
+    /// Given in bytes, optionally followed by K, M, G or T (e.g. '512M').
+    /// The memory already allocated by WorMod is accounted against this limit
+    /// every time the available memory is checked. Regardless of this option,
+    /// the memory limit of the cgroup WorMod runs in (either v1 or v2) is
+    /// detected and respected, as well as the memory available on the system.
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    pub memory_limit: Option<usize>,
+    /// The number of threads used to manipulate the wordlist
+    ///
+    /// When the whole wordlist is stored in memory, splitting the input into
+    /// entries, the operations applied to each entry on its own and sorting
+    /// are spread across the threads, as well as sorting the chunks of entries
+    /// spilled to disk. The output is the same regardless of the number of
+    /// threads.
+    #[arg(long, value_name="N", default_value_t=1)]
+    pub threads: usize,
     /// Reverse each entry (not the wordlist itself)
     #[arg(long, action=clap::ArgAction::SetTrue)]
-    pub(crate) reverse: bool,
+    pub reverse: bool,
     /// Discard entries shorter than the given length
     #[arg(long, value_name="N", action=clap::ArgAction::Set)]
-    pub(crate) min_len: Option<usize>,
+    pub min_len: Option<usize>,
     /// Discard entries longer than the given length
     #[arg(long, value_name="N", action=clap::ArgAction::Set)]
-    pub(crate) max_len: Option<usize>,
+    pub max_len: Option<usize>,
+    /// The unit in which the length of the entries is measured
+    ///
+    /// Used by --min-len and --max-len, as well as by the rules rejecting
+    /// entries based on their length. Characters are Unicode scalar values,
+    /// while graphemes are what is perceived as a single character (e.g.
+    /// a letter followed by a combining accent).
+    /// Can only be bytes when using --bytes.
+    #[arg(long, value_name="UNIT", value_enum, default_value_t=LengthUnit::Bytes)]
+    pub length_unit: LengthUnit,
+    /// Discard entries not matching the given regular expression
+    ///
+    /// Can be used multiple times. See --match-mode for how multiple
+    /// expressions are combined.
+    #[arg(long="match", value_name="REGEX", value_parser=Regex::new)]
+    pub match_patterns: Vec<Regex>,
+    /// Discard entries matching the given regular expression
+    ///
+    /// Can be used multiple times. See --match-mode for how multiple
+    /// expressions are combined.
+    #[arg(long="exclude", value_name="REGEX", value_parser=Regex::new)]
+    pub exclude_patterns: Vec<Regex>,
+    /// How multiple --match and --exclude expressions are combined
+    ///
+    /// With 'any', entries are kept if they match at least one of the --match
+    /// expressions and discarded if they match at least one of the --exclude
+    /// expressions. With 'all', entries are kept only if they match every
+    /// --match expression and discarded only if they match every --exclude
+    /// expression.
+    #[arg(long, value_name="MODE", value_enum, default_value_t=MatchMode::Any)]
+    pub match_mode: MatchMode,
+    /// Apply the rules contained in the given file to each entry
+    ///
+    /// The rules file shall contain one rule per line, written in the syntax
+    /// given by --rules-syntax. Empty lines and lines starting with '#' are
+    /// ignored.
+    /// Each entry is replaced by the candidates resulting from applying every
+    /// rule to it, in the same order as the rules appear in the file.
+    /// Candidates rejected by a rule are discarded.
+    /// Memory functions are not supported.
+    #[arg(long, value_name="FILE")]
+    pub rules: Option<PathBuf>,
+    /// The syntax of the rules file
+    ///
+    /// When using the John the Ripper syntax, the preprocessor is applied
+    /// to each rule before parsing it, except for parallel ranges and
+    /// back-references which are not supported, like the numeric constants
+    /// other than 'z'. If the file contains [List.Rules:NAME] sections, only
+    /// the rules inside of them are loaded.
+    #[arg(long, value_name="SYNTAX", value_enum, default_value_t=RulesSyntax::Hashcat)]
+    pub rules_syntax: RulesSyntax,
+    /// Only load the rules of the given [List.Rules:NAME] section
+    ///
+    /// Can only be used in conjunction with --rules-syntax john.
+    /// Omit to load the rules of all the sections.
+    #[arg(long, value_name="NAME")]
+    pub rules_section: Option<String>,
+    /// Read the whole job from a pipeline file
+    ///
+    /// The file is written in TOML and describes the inputs, the output and
+    /// the operations to apply. Options are named as on the command line,
+    /// without the leading dashes. Each operation goes in its own [[operation]]
+    /// table along with its parameters, and operations are applied in the same
+    /// order as their tables. Any other option goes at the top level. Relative
+    /// paths are resolved against the directory of the pipeline file.
+    /// Cannot be used in conjunction with any other option.
+    #[arg(long, value_name="FILE", exclusive=true)]
+    pub pipeline: Option<PathBuf>,
+    /// The operations to apply, in order
+    #[arg(skip)]
+    pub operations: Vec<OperationKind>,
+}
+
+/// The operations available to combine multiple wordlists
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum SetOperation {
+    /// Entries contained in any of the inputs
+    Union,
+    /// Entries contained in all the inputs
+    Intersection,
+    /// Entries of the first input not contained in the other ones
+    Difference,
+    /// Entries contained in exactly one of the inputs
+    SymmetricDifference,
+}
+
+/// The policies available to handle invalid UTF-8 input
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum InvalidUtf8Policy {
+    /// Skip the line
+    Skip,
+    /// Replace the invalid sequences with U+FFFD
+    Replace,
+    /// Encode the whole line as $HEX[...] once manipulated
+    Hex,
+    /// Exit-fail
+    Fail,
+}
+
+/// The character encodings available for the input and output
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TextEncoding {
+    /// UTF-8
+    #[value(alias="utf-8")]
+    Utf8,
+    /// ISO-8859-1
+    #[value(alias="iso-8859-1")]
+    Latin1,
+    /// Windows-1252
+    #[value(alias="windows-1252", alias="cp1252")]
+    Windows1252,
+    /// Windows-1251
+    #[value(alias="windows-1251", alias="windows1251")]
+    Cp1251,
+    /// UTF-16, little endian
+    #[value(alias="utf-16le")]
+    Utf16le,
+    /// UTF-16, big endian
+    #[value(alias="utf-16be")]
+    Utf16be,
+}
+
+/// The policies available to handle entries which are not
+/// representable in the output encoding
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum UnrepresentablePolicy {
+    /// Skip the entry
+    Skip,
+    /// Replace the unrepresentable characters with '?'
+    Replace,
+    /// Encode the UTF-8 bytes of the whole entry as $HEX[...]
+    Hex,
+    /// Exit-fail
+    Fail,
+}
+
+/// The compression formats available for the input and output
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum CompressionFormat {
+    /// No compression
+    None,
+    /// gzip
+    Gzip,
+    /// bzip2
+    Bzip2,
+    /// xz
+    Xz,
+    /// Zstandard
+    Zstd,
+}
+
+/// The hashing algorithms available to remove duplicates
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum HashAlgorithm {
+    /// SipHash 1-3, resistant to collision attacks
+    Siphash,
+    /// 64-bit FNV-1a
+    Fnv1a,
+}
+
+/// The ways multiple regular expressions can be combined
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum MatchMode {
+    /// At least one expression shall match
+    Any,
+    /// Every expression shall match
+    All,
+}
+
+/// The operations which can be applied to the wordlist
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperationKind {
+    /// Apply the rules, see --rules
+    Rules,
+    /// Filter by length, see --min-len and --max-len
+    Length,
+    /// Filter by regular expressions, see --match and --exclude
+    Patterns,
+    /// Reverse each entry, see --reverse
+    Reverse,
+    /// Remove duplicates, see --unique
+    Unique,
+    /// Remove duplicates approximately, see --unique-approx
+    UniqueApprox,
+    /// Sort the wordlist, see --sort
+    Sort,
+}
+
+impl OperationKind {
+    /// The arguments requesting each operation
+    pub(crate) const ARGS : [(OperationKind, &'static [&'static str]); 7] = [
+        (OperationKind::Rules, &["rules"]),
+        (OperationKind::Length, &["min_len", "max_len"]),
+        (OperationKind::Patterns, &["match_patterns", "exclude_patterns"]),
+        (OperationKind::Reverse, &["reverse"]),
+        (OperationKind::Unique, &["unique"]),
+        (OperationKind::UniqueApprox, &["unique_approx"]),
+        (OperationKind::Sort, &["sort"]),
+    ];
+}
+
+/// The units available to measure the length of the entries
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum LengthUnit {
+    /// Bytes of the UTF-8 encoding
+    Bytes,
+    /// Unicode scalar values
+    Chars,
+    /// Extended grapheme clusters
+    Graphemes,
+}
+
+/// The syntaxes available for the rules files
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum RulesSyntax {
+    /// hashcat rules
+    Hashcat,
+    /// John the Ripper wordlist rules
+    John,
 }
 
 impl Params {
+    /// Parses the given arguments, the first one being the program name
+    ///
+    /// The parameters are read from the pipeline file, if given
+    pub fn from_args<I, T>(args: I) -> Result<Self, WormodError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        Self::parse_args(args)
+            .map_err(invalid_arguments)?
+            .with_pipeline_file()
+    }
+
+    /// Builds the parameters with all the options set to their defaults
+    pub(crate) fn defaults() -> Self {
+        Self::parse_from([env!("CARGO_PKG_NAME")])
+    }
+
+    /// Replaces the parameters with those of the pipeline file, if any
+    pub(crate) fn with_pipeline_file(self) -> Result<Self, WormodError> {
+        match self.pipeline.as_ref() {
+            Some(path) => Params::from_pipeline_file(path),
+            None => Ok(self),
+        }
+    }
+
+    /// Parses the arguments
+    fn parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        Self::from_matches(&Params::command().try_get_matches_from(args)?)
+    }
+
+    /// Builds the parameters from the parsed arguments
+    ///
+    /// The requested operations are recorded in the order of their
+    /// first occurrence among the arguments
+    pub(crate) fn from_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut params = Params::from_arg_matches(matches)?;
+        let mut operations : Vec<(usize, OperationKind)> = OperationKind::ARGS.iter()
+            .filter_map(|(kind, ids)| {
+                ids.iter()
+                    .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+                    .filter_map(|id| matches.index_of(id))
+                    .min()
+                    .map(|index| (index, *kind))
+            })
+            .collect();
+        operations.sort_unstable_by_key(|(index, _)| *index);
+        params.operations = operations.into_iter().map(|(_, kind)| kind).collect();
+        Ok(params)
+    }
+
     /// Checks the options to ensure they are consistent
-    pub(crate) fn validate(mut self) -> Self {
-        self.validate_paths();
-        self.validate_length_range();
-        self.validate_operations();
-        self
+    pub fn validate(mut self) -> Result<Self, WormodError> {
+        self.validate_paths()?;
+        self.validate_rules_path()?;
+        self.validate_tmp_dir()?;
+        self.validate_length_range()?;
+        self.validate_operations()?;
+        Ok(self)
     }
 
     /// Checks the input and output paths to ensure they are consistent
-    fn validate_paths(&mut self) {
-        self.validate_input_path();
-        self.validate_output_path();
-        if let (Some(in_path), Some(out_path)) = (self.input.as_ref(), self.output.as_ref()) {
-            if in_path == out_path {
-                exit_err!(
+    pub(crate) fn validate_paths(&mut self) -> Result<(), WormodError> {
+        self.validate_input_path()?;
+        self.validate_output_path()?;
+        if let Some(out_path) = self.output.as_ref() {
+            if let Some(in_path) = self.input.iter().find(|p| *p == out_path) {
+                return Err(error!(
+                    Path,
                     ("Input and output paths resolve to the same resource: {:?}", in_path)
-                );
+                ));
             }
         }
+        Ok(())
     }
 
-    /// Checks the intput path and canonicalizes it
-    pub(crate) fn validate_input_path(&mut self) {
-        if self.input.is_none() {
-            return;
-        }
-        let p = self.input.clone().unwrap();
-        if self.no_follow_symlinks && p.contains_symlinks() {
-            exit_err!(
+    /// Checks the intput paths and canonicalizes them
+    pub(crate) fn validate_input_path(&mut self) -> Result<(), WormodError> {
+        self.input = self.input.iter()
+            .map(|p| self.validated_input_path(p))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// Checks an intput path and returns its canonical form
+    fn validated_input_path(&self, p: &PathBuf) -> Result<PathBuf, WormodError> {
+        if self.no_follow_symlinks && p.contains_symlinks()? {
+            return Err(error!(
+                Path,
                 ("Input path contains symlinks: {:?}", p)
-            );
+            ));
         }
         match p.canonicalize() {
             Err(e) => {
-                exit_err!(
+                Err(error!(
+                    Path,
                     ("Failed to resolve input path: {:?}", p),
                     ("Failed to canonicalize: {}", e.to_string())
-                );
+                ))
             },
             Ok(path) => {
                 match std::fs::exists(path.clone()) {
                     Err(e) => {
-                        exit_err!(
+                        Err(error!(
+                            Path,
                             ("Failed to validate input path: {:?}", path),
                             ("Error while checking for existence: {}", e.to_string())
-                        );
+                        ))
                     },
                     Ok(false) => {
-                        exit_err!(
+                        Err(error!(
+                            Path,
                             ("Input wordlist not found at path: {:?}", p)
-                        );
+                        ))
                     },
                     Ok(true) => {
                         if path.is_dir() {
-                            exit_err!(
+                            return Err(error!(
+                                Path,
                                 ("Input path is a directory: {:?}", p)
-                            );
+                            ));
                         }
-                        self.input = Some(path);
+                        Ok(path)
                     },
                 }
             }
@@ -141,192 +614,482 @@ impl Params {
     }
 
     /// Checks the output path and canonicalizes it
-    pub(crate) fn validate_output_path(&mut self) {
+    pub(crate) fn validate_output_path(&mut self) -> Result<(), WormodError> {
         if self.output.is_none() {
-            return;
+            return Ok(());
         }
-        let ref p = self.output.clone().unwrap();
-        if self.no_follow_symlinks && p.contains_symlinks() {
-            exit_err!(
+        let p = &self.output.clone().unwrap();
+        if self.no_follow_symlinks && p.contains_symlinks()? {
+            return Err(error!(
+                Path,
                 ("Output path contains symlinks: {:?}", p)
-            );
+            ));
         } else if p.is_dir() {
-            exit_err!(
+            return Err(error!(
+                Path,
                 ("Output path is a directory: {:?}", p)
-            );
+            ));
         }
         match std::fs::exists(p) {
             Err(e) => {
-                exit_err!(
+                return Err(error!(
+                    Path,
                     ("Failed to validate output path: {:?}", p),
                     ("Error while checking for existence: {}", e.to_string())
-                );
+                ));
             },
             Ok(true) => {
-                self.output = std::fs::canonicalize(p)
-                    .map_err(|e| {
-                        exit_err!(
-                            ("Failed to resolve output path: {:?}", p),
-                            ("Failed to canonicalize: {}", e.to_string())
-                        );
-                    }).ok();
+                let path = std::fs::canonicalize(p)
+                    .map_err(|e| error!(
+                        Path,
+                        ("Failed to resolve output path: {:?}", p),
+                        ("Failed to canonicalize: {}", e.to_string())
+                    ))?;
+                self.output = Some(path);
             },
             Ok(false) => match p.parent() {
                 Some(dir) => {
-                    let file = p.file_name().unwrap_or_else(|| {
-                        exit_err!(
+                    let Some(file) = p.file_name() else {
+                        return Err(error!(
+                            Path,
                             ("Failed to get file name in output path: {:?}", p)
-                        );
-                    });
-                    self.output = std::fs::canonicalize(dir)
-                        .map_err(|e| {
-                            exit_err!(
-                                ("Failed to resolve output path component: {:?}", dir),
-                                ("Failed to canonicalize parent directory: {}", e.to_string())
-                            );
-                        }).map(|d| {
-                            d.join(file)
-                        }).ok();
+                        ));
+                    };
+                    // a bare file name has an empty parent
+                    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+                    let dir = std::fs::canonicalize(dir)
+                        .map_err(|e| error!(
+                            Path,
+                            ("Failed to resolve output path component: {:?}", dir),
+                            ("Failed to canonicalize parent directory: {}", e.to_string())
+                        ))?;
+                    self.output = Some(dir.join(file));
                 },
                 None => {
-                    exit_err!(
+                    return Err(error!(
+                        Path,
                         ("Unexpected output path: {:?}", p)
-                    );
+                    ));
                 }
             },
         }
+        Ok(())
+    }
+
+    /// Checks the rules path and canonicalizes it
+    fn validate_rules_path(&mut self) -> Result<(), WormodError> {
+        if self.rules.is_none() {
+            return Ok(());
+        }
+        let p = self.rules.clone().unwrap();
+        if self.no_follow_symlinks && p.contains_symlinks()? {
+            return Err(error!(
+                Path,
+                ("Rules path contains symlinks: {:?}", p)
+            ));
+        }
+        match p.canonicalize() {
+            Err(e) => {
+                return Err(error!(
+                    Path,
+                    ("Failed to resolve rules path: {:?}", p),
+                    ("Failed to canonicalize: {}", e.to_string())
+                ));
+            },
+            Ok(path) => {
+                if path.is_dir() {
+                    return Err(error!(
+                        Path,
+                        ("Rules path is a directory: {:?}", p)
+                    ));
+                }
+                self.rules = Some(path);
+            },
+        }
+        Ok(())
+    }
+
+    /// Checks the temporary directory and canonicalizes it
+    fn validate_tmp_dir(&mut self) -> Result<(), WormodError> {
+        if let Some(p) = self.tmp_dir.as_ref() {
+            if !p.is_dir() {
+                return Err(error!(
+                    Path,
+                    ("Temporary path is not a directory: {:?}", p)
+                ));
+            }
+            let path = std::fs::canonicalize(p)
+                .map_err(|e| error!(
+                    Path,
+                    ("Failed to resolve temporary path: {:?}", p),
+                    ("Failed to canonicalize: {}", e.to_string())
+                ))?;
+            self.tmp_dir = Some(path);
+        }
+        Ok(())
     }
 
     /// Checks the length range to ensure it is consistent
-    fn validate_length_range(&self) {
+    fn validate_length_range(&self) -> Result<(), WormodError> {
         match (self.min_len, self.max_len) {
             (Some(min), Some(max)) => {
                 if max < min {
-                    exit_err!(
+                    return Err(error!(
+                        InvalidOptions,
                         ("Invalid min-max length values: {}-{}", min, max),
                         ("Maximum length cannot be smaller than minimum length")
-                    );
+                    ));
                 } else if min == usize::MAX {
-                    exit_err!(
-                        ("Invalid min length: {}", max),
+                    return Err(error!(
+                        InvalidOptions,
+                        ("Invalid min length: {}", min),
                         ("This is equivalent to a no-op")
-                    );
+                    ));
                 } else if max == 0 {
-                    exit_err!(
+                    return Err(error!(
+                        InvalidOptions,
                         ("Invalid max length: {}", max),
                         ("This is equivalent to a no-op")
-                    );
+                    ));
                 }
             },
             (Some(min), None) => {
                 if min == usize::MAX {
-                    exit_err!(
+                    return Err(error!(
+                        InvalidOptions,
                         ("Invalid min length: {}", min),
                         ("This is equivalent to a no-op")
-                    );
+                    ));
                 }
             },
             (None, Some(max)) => {
                 if max == 0 {
-                    exit_err!(
+                    return Err(error!(
+                        InvalidOptions,
                         ("Invalid max length: {}", max),
                         ("This is equivalent to a no-op")
-                    );
+                    ));
                 }
             },
             (None, None) => (),
         }
+        Ok(())
     }
 
     /// Checks the scheduled operations to ensure they are consistent
-    fn validate_operations(&self) {
-        if !self.sort && !self.unique && !self.reverse && self.min_len.is_none() && self.max_len.is_none() {
-            exit_err!(
+    fn validate_operations(&self) -> Result<(), WormodError> {
+        if !self.has_manipulations() {
+            return Err(error!(
+                InvalidOptions,
                 ("No manipulation option is set"),
                 ("This is equivalent to a no-op")
-            );
+            ));
         } else if self.output.is_none() && self.append_output {
-            exit_err!(
-                ("Incompatible option: --append-output"),
-                ("Cannot use append to a file without an output file")
-            );
-        } else if self.output.is_none() && self.append_output {
-            exit_err!(
+            return Err(error!(
+                InvalidOptions,
                 ("Incompatible option: --append-output"),
                 ("Cannot append to a file without an output file")
-            );
-        } else if self.pipe && self.sort {
-            exit_err!(
-                ("Incompatible options: --pipe --sort"),
-                ("Cannot sort a pipe flow")
-            );
+            ));
+        } else if self.input.len() > 1 && self.set_operation.is_none() {
+            return Err(error!(
+                InvalidOptions,
+                ("Too many input wordlists: {}", self.input.len()),
+                ("Multiple inputs can only be used in conjunction with --set-operation")
+            ));
+        } else if self.input.len() < 2 && self.set_operation.is_some() {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --set-operation"),
+                ("Cannot combine less than two input wordlists")
+            ));
+        } else if self.sorted_inputs && self.set_operation.is_none() {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --sorted-inputs"),
+                ("Can only be used in conjunction with --set-operation")
+            ));
+        } else if self.pipe && self.set_operation.is_some() && !self.sorted_inputs {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --pipe --set-operation"),
+                ("Cannot combine unsorted inputs in pipe mode, see --sorted-inputs")
+            ));
+        } else if self.rules.is_none() && self.rules_section.is_some() {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --rules-section"),
+                ("Cannot select a rules section without a rules file")
+            ));
+        } else if !matches!(self.rules_syntax, RulesSyntax::John) && self.rules_section.is_some() {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --rules-section"),
+                ("Rules sections are only available with the John the Ripper syntax")
+            ));
+        } else if self.unique_approx && !self.pipe {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --unique-approx"),
+                ("Can only be used in conjunction with --pipe")
+            ));
+        } else if self.unique_approx && self.unique {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --unique --unique-approx"),
+                ("Cannot remove duplicates both exactly and approximately")
+            ));
+        } else if self.unique_approx && self.sort {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --sort --unique-approx"),
+                ("Sorting already requires to store all the entries, use --unique instead")
+            ));
+        } else if self.approx_capacity == 0 {
+            return Err(error!(
+                InvalidOptions,
+                ("Invalid approximate capacity: {}", self.approx_capacity),
+                ("Capacity must be greater than zero")
+            ));
+        } else if !(self.approx_error_rate > 0.0 && self.approx_error_rate < 1.0) {
+            return Err(error!(
+                InvalidOptions,
+                ("Invalid approximate error rate: {}", self.approx_error_rate),
+                ("Error rate must be between 0 and 1, both excluded")
+            ));
+        } else if self.keep_last && !self.unique {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --keep-last"),
+                ("Can only be used in conjunction with --unique")
+            ));
+        } else if self.keep_last && self.sort {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --keep-last --sort"),
+                ("Sorted duplicates are indistinguishable, this is equivalent to a no-op")
+            ));
+        } else if self.keep_last && self.pipe {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --keep-last --pipe"),
+                ("Cannot know the last occurrence of an entry before the EOF")
+            ));
+        } else if self.keep_order && !self.unique {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --keep-order"),
+                ("Can only be used in conjunction with --unique")
+            ));
+        } else if self.keep_order && self.sort {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --keep-order --sort"),
+                ("Sorted entries do not retain their original order")
+            ));
+        } else if self.keep_order && self.pipe {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --keep-order --pipe"),
+                ("Entries are always written in their original order in pipe mode")
+            ));
+        } else if self.tmp_dir.is_some() && !self.sort && !self.unique {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible option: --tmp-dir"),
+                ("Temporary files are only used in conjunction with --sort or --unique")
+            ));
+        } else if self.bytes && self.length_unit != LengthUnit::Bytes {
+            return Err(error!(
+                InvalidOptions,
+                ("Incompatible options: --bytes --length-unit"),
+                ("Raw entries can only be measured in bytes")
+            ));
+        } else if self.memory_limit == Some(0) {
+            return Err(error!(
+                InvalidOptions,
+                ("Invalid memory limit: 0"),
+                ("The limit must be greater than zero")
+            ));
+        } else if self.threads == 0 {
+            return Err(error!(
+                InvalidOptions,
+                ("Invalid number of threads: 0"),
+                ("At least one thread is needed")
+            ));
         }
+        Ok(())
     }
 
-    /// Repeats the checks on the input path to try to ensure consistency
-    pub(crate) fn check_input_path(&self) {
-        if let Some(p) = self.input.as_ref() {
+    /// Repeats the checks on the input paths to try to ensure consistency
+    pub(crate) fn check_input_path(&self) -> Result<(), WormodError> {
+        for p in self.input.iter() {
             if !p.exists() {
-                exit_err!(
+                return Err(error!(
+                    Path,
                     ("Input wordlist not found at path: {:?}", p)
-                );
+                ));
             } else if p.is_dir() {
-                exit_err!(
+                return Err(error!(
+                    Path,
                     ("Input path is a directory: {:?}", p)
-                );
-            } else if self.no_follow_symlinks && p.contains_symlinks() {
-                exit_err!(
+                ));
+            } else if self.no_follow_symlinks && p.contains_symlinks()? {
+                return Err(error!(
+                    Path,
                     ("Input path contains symlinks: {:?}", p)
-                );
+                ));
             }
         }
+        Ok(())
     }
 
     /// Repeats the checks on the output path to try to ensure consistency
-    pub(crate) fn check_output_path(&self) {
+    pub(crate) fn check_output_path(&self) -> Result<(), WormodError> {
         if let Some(p) = self.output.as_ref() {
             if p.is_dir() {
-                exit_err!(
+                return Err(error!(
+                    Path,
                     ("Output path is a directory: {:?}", p)
-                );
-            } else if self.no_follow_symlinks && p.contains_symlinks() {
-                exit_err!(
+                ));
+            } else if self.no_follow_symlinks && p.contains_symlinks()? {
+                return Err(error!(
+                    Path,
                     ("Output path contains symlinks: {:?}", p)
-                );
+                ));
             }
         }
+        Ok(())
+    }
+
+    /// Whether any manipulation shall be performed
+    fn has_manipulations(&self) -> bool {
+        self.sort || self.unique || self.unique_approx || self.reverse
+            || self.has_length_range() || self.has_patterns()
+            || self.rules.is_some() || self.set_operation.is_some()
+    }
+
+    /// The compression format of the output
+    pub(crate) fn output_compression(&self) -> CompressionFormat {
+        if let Some(format) = self.compress {
+            return format;
+        }
+        self.output.as_ref()
+            .map_or(CompressionFormat::None, |p| CompressionFormat::from_path(p))
     }
 
     /// Whether the entries shall be filtered by length
     pub(crate) fn has_length_range(&self) -> bool {
         self.min_len.is_some() || self.max_len.is_some()
     }
+
+    /// Whether the entries shall be filtered by regular expressions
+    pub(crate) fn has_patterns(&self) -> bool {
+        !self.match_patterns.is_empty() || !self.exclude_patterns.is_empty()
+    }
+}
+
+/// Parses a size given in bytes, optionally followed by a binary multiple
+///
+/// Multiples are K, M, G and T, optionally followed by 'iB' or 'B'
+pub(crate) fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number : f64 = number.parse().map_err(|_| format!("invalid size: {}", size))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let multiple : u64 = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("unknown unit: {}", unit)),
+    };
+    let bytes = number * multiple as f64;
+    if bytes >= usize::MAX as f64 {
+        return Err(format!("size too large: {}", size));
+    }
+    Ok(bytes as usize)
+}
+
+/// Turns a failure to parse the arguments into an error
+///
+/// Only the first line of the message of clap is kept
+pub(crate) fn invalid_arguments(e: clap::Error) -> WormodError {
+    let message = e.to_string();
+    let summary = message.lines().next().unwrap_or_default();
+    error!(InvalidOptions, ("{}", summary.trim_start_matches("error: ")))
 }
 
 trait PathOps {
     /// Checks all the components of a path to spot symlinks
-    fn contains_symlinks(&self) -> bool;
+    ///
+    /// Components which do not exist yet cannot be symlinks
+    fn contains_symlinks(&self) -> Result<bool, WormodError>;
 }
 
 impl PathOps for PathBuf {
-    fn contains_symlinks(&self) -> bool {
+    fn contains_symlinks(&self) -> Result<bool, WormodError> {
         let mut path = PathBuf::new();
         for component in self.components() {
             path.push(component);
             match std::fs::symlink_metadata(&path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
                 Err(e) => {
-                    exit_err!(
-                        ("Failed to validate output path component: {:?}", path),
+                    return Err(error!(
+                        Path,
+                        ("Failed to validate path component: {:?}", path),
                         ("Failed to check symlink: {}", e.to_string())
-                    );
+                    ));
                 },
                 Ok(md) => {
                     if md.is_symlink() {
-                        return true;
+                        return Ok(true);
                     }
                 },
             }
         }
-        false
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operations(args: &[&str]) -> Vec<OperationKind> {
+        let args = std::iter::once("wormod").chain(args.iter().copied());
+        Params::parse_args(args).unwrap().operations
+    }
+
+    #[test]
+    fn operations_in_command_line_order() {
+        assert_eq!(operations(&[]), []);
+        assert_eq!(
+            operations(&["--sort", "--reverse", "--min-len", "3"]),
+            [OperationKind::Sort, OperationKind::Reverse, OperationKind::Length]
+        );
+        assert_eq!(
+            operations(&["--max-len=8", "--unique", "--min-len=2", "--reverse"]),
+            [OperationKind::Length, OperationKind::Unique, OperationKind::Reverse]
+        );
+        assert_eq!(
+            operations(&["-i", "a.txt", "--exclude", "x", "--unique-approx", "--match", "y"]),
+            [OperationKind::Patterns, OperationKind::UniqueApprox]
+        );
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("2 MiB"), Ok(2 << 20));
+        assert_eq!(parse_size("1.5g"), Ok(3 << 29));
+        assert_eq!(parse_size("1TB"), Ok(1 << 40));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("12X").is_err());
+        assert!(parse_size("1.2.3M").is_err());
+        assert!(parse_size("99999999999T").is_err());
     }
 }
@@ -0,0 +1,244 @@
+use crate::error::*;
+use crate::params::OperationKind;
+use crate::params::Params;
+
+use clap::CommandFactory;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::Path;
+use toml::Table;
+use toml::Value;
+
+/// The name of the tables describing the operations
+const OPERATION_TABLE : &str = "operation";
+
+/// How an option of the command line takes its value
+struct OptionKind {
+    /// Whether the option is a flag, thus takes no value
+    flag: bool,
+    /// Whether the value is a path
+    path: bool,
+    /// The operation requested by the option, if any
+    operation: Option<OperationKind>,
+}
+
+impl Params {
+    /// Reads the parameters from a pipeline file
+    ///
+    /// The content of the file is translated into the equivalent command
+    /// line arguments, which are then parsed as usual
+    pub(crate) fn from_pipeline_file(path: &Path) -> Result<Self, WormodError> {
+        let content = std::fs::read_to_string(path).map_err(|e| error!(
+            Io,
+            ("Failed to read pipeline file: {:?}", path),
+            ("Reason of the failure: {}", e.to_string())
+        ))?;
+        let table = content.parse::<Table>().map_err(|e| error!(
+            InvalidOptions,
+            ("Failed to parse pipeline file: {:?}", path),
+            ("{}", e.message())
+        ))?;
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let options = command_line_options();
+
+        let mut args = vec![OsString::from(env!("CARGO_PKG_NAME"))];
+        for (key, value) in table.iter().filter(|(key, _)| *key != OPERATION_TABLE) {
+            let option = find_option(&options, key)?;
+            if option.operation.is_some() {
+                return Err(error!(
+                    InvalidOptions,
+                    ("Operation outside of an [[{}]] table: {}", OPERATION_TABLE, key),
+                    ("Operations must be listed in the order they shall be applied")
+                ));
+            }
+            push_args(&mut args, key, value, option, base_dir)?;
+        }
+
+        let operations = match table.get(OPERATION_TABLE) {
+            None => &Vec::new(),
+            Some(Value::Array(operations)) => operations,
+            Some(_) => {
+                return Err(error!(
+                    InvalidOptions,
+                    ("Invalid pipeline file: {:?}", path),
+                    ("Operations must be listed as [[{}]] tables", OPERATION_TABLE)
+                ));
+            },
+        };
+        let mut requested : Vec<OperationKind> = Vec::new();
+        for (n, operation) in operations.iter().enumerate() {
+            let Value::Table(operation) = operation else {
+                return Err(error!(
+                    InvalidOptions,
+                    ("Invalid pipeline file: {:?}", path),
+                    ("Operations must be listed as [[{}]] tables", OPERATION_TABLE)
+                ));
+            };
+            let mut kinds : Vec<OperationKind> = Vec::new();
+            for (key, value) in operation.iter() {
+                let option = find_option(&options, key)?;
+                // flags set to false are not given at all
+                let unset = option.flag && matches!(value, Value::Boolean(false));
+                if let Some(kind) = option.operation.filter(|_| !unset) {
+                    if !kinds.contains(&kind) {
+                        kinds.push(kind);
+                    }
+                }
+            }
+            match kinds[..] {
+                [kind] if requested.contains(&kind) => {
+                    return Err(error!(
+                        InvalidOptions,
+                        ("Invalid operation #{} in pipeline file: {:?}", n + 1, path),
+                        ("The same operation cannot be requested more than once")
+                    ));
+                },
+                [kind] => requested.push(kind),
+                [] => {
+                    return Err(error!(
+                        InvalidOptions,
+                        ("Invalid operation #{} in pipeline file: {:?}", n + 1, path),
+                        ("The table does not request any operation")
+                    ));
+                },
+                _ => {
+                    return Err(error!(
+                        InvalidOptions,
+                        ("Invalid operation #{} in pipeline file: {:?}", n + 1, path),
+                        ("Each table shall request exactly one operation")
+                    ));
+                },
+            }
+            for (key, value) in operation.iter() {
+                push_args(&mut args, key, value, find_option(&options, key)?, base_dir)?;
+            }
+        }
+
+        Params::from_args(args).map_err(|e| error!(
+            InvalidOptions,
+            ("Invalid pipeline file: {:?}", path),
+            ("{}", e.message().summary)
+        ))
+    }
+}
+
+/// Collects the options available on the command line, by name
+fn command_line_options() -> HashMap<String, OptionKind> {
+    let command = Params::command();
+    command.get_arguments()
+        .filter(|arg| arg.get_id() != "pipeline")
+        .filter_map(|arg| {
+            let long = arg.get_long()?;
+            let id = arg.get_id().as_str();
+            let kind = OptionKind {
+                flag: !arg.get_action().takes_values(),
+                path: arg.get_value_names()
+                    .is_some_and(|names| names.iter().any(|n| n == "FILE" || n == "DIR")),
+                operation: OperationKind::ARGS.iter()
+                    .find(|(_, ids)| ids.contains(&id))
+                    .map(|(kind, _)| *kind),
+            };
+            Some((long.to_owned(), kind))
+        })
+        .collect()
+}
+
+/// Looks up the option with the given name
+fn find_option<'a>(options: &'a HashMap<String, OptionKind>, key: &str) -> Result<&'a OptionKind, WormodError> {
+    options.get(key).ok_or_else(|| error!(
+        InvalidOptions,
+        ("Unknown option in pipeline file: {}", key),
+        ("Options are named as on the command line, without the leading dashes")
+    ))
+}
+
+/// Appends the command line arguments equivalent to the option
+///
+/// Arrays are turned into the option being repeated for each of their
+/// elements, and flags are only given if set to true
+fn push_args(args: &mut Vec<OsString>, key: &str, value: &Value, option: &OptionKind, base_dir: &Path) -> Result<(), WormodError> {
+    let values = match value {
+        Value::Array(values) => values.iter().collect(),
+        _ => vec![value],
+    };
+    for value in values.into_iter() {
+        let value = match value {
+            Value::Boolean(set) if option.flag => {
+                if *set {
+                    args.push(format!("--{}", key).into());
+                }
+                continue;
+            },
+            Value::String(s) if option.path => base_dir.join(s).into_os_string(),
+            Value::String(s) => s.into(),
+            Value::Integer(n) if !option.flag => n.to_string().into(),
+            Value::Float(f) if !option.flag => f.to_string().into(),
+            Value::Boolean(b) if !option.flag => b.to_string().into(),
+            _ => {
+                return Err(error!(
+                    InvalidOptions,
+                    ("Invalid value in pipeline file for {}: {}", key, value.type_str()),
+                    ("{}", if option.flag { "Expected true or false" } else { "Expected a string or a number" })
+                ));
+            },
+        };
+        let mut arg = OsString::from(format!("--{}=", key));
+        arg.push(value);
+        args.push(arg);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wormod::testing::TempDir;
+
+    /// Reads the parameters from a pipeline file, written next to
+    /// an input wordlist and a rules file
+    fn from_pipeline(tmp_dir: &TempDir, pipeline: &str) -> Result<Params, WormodError> {
+        tmp_dir.file("input.txt", "word\n");
+        tmp_dir.file("best.rule", ":\n");
+        Params::from_pipeline_file(&tmp_dir.file("pipeline.toml", pipeline))
+    }
+
+    #[test]
+    fn operations_in_order() {
+        let tmp_dir = TempDir::new();
+        let params = from_pipeline(&tmp_dir, concat!(
+            "input = \"input.txt\"\n",
+            "[[operation]]\nsort = true\n",
+            "[[operation]]\nrules = \"best.rule\"\n",
+            "[[operation]]\nmin-len = 4\nmax-len = 8\n",
+        )).unwrap();
+        assert!(params.operations == [OperationKind::Sort, OperationKind::Rules, OperationKind::Length]);
+        assert_eq!(params.input, [tmp_dir.path().join("input.txt")]);
+        assert_eq!(params.rules, Some(tmp_dir.path().join("best.rule")));
+        assert_eq!((params.min_len, params.max_len), (Some(4), Some(8)));
+    }
+
+    #[test]
+    fn flags_set_to_false() {
+        let tmp_dir = TempDir::new();
+        let params = from_pipeline(&tmp_dir, "input = \"input.txt\"\n[[operation]]\nreverse = false\n");
+        assert!(params.is_err());
+        let params = from_pipeline(&tmp_dir, "input = \"input.txt\"\n[[operation]]\nsort = true\nreverse = false\n").unwrap();
+        assert!(params.operations == [OperationKind::Sort]);
+        assert!(!params.reverse);
+    }
+
+    #[test]
+    fn invalid_pipelines() {
+        let tmp_dir = TempDir::new();
+        let pipelines = [
+            "input = \"input.txt\"\nsort = true\n",
+            "input = \"input.txt\"\n[[operation]]\nsort = true\nunique = true\n",
+            "input = \"input.txt\"\n[[operation]]\nsort = true\n[[operation]]\nsort = true\n",
+            "input = \"input.txt\"\n[[operation]]\nsort = 1\n",
+            "input = \"input.txt\"\n[[operation]]\nunknown = true\n",
+        ];
+        for pipeline in pipelines {
+            assert!(from_pipeline(&tmp_dir, pipeline).is_err(), "{}", pipeline);
+        }
+    }
+}
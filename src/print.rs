@@ -1,24 +1,32 @@
+use crate::error::WormodError;
 
-#[macro_export]
-macro_rules! exit_err {
+macro_rules! print_info {
     ($($t:tt),+) => {
-        eprintln!("\x1b[91mError:\x1b[0m");
-        private_err_descr!($($t),*);
-        std::process::exit(1);
+        eprintln!("\x1b[94mInfo:\x1b[0m");
+        private_info_descr!($($t),*);
     };
 }
 
-#[macro_export]
-macro_rules! private_err_descr {
+macro_rules! private_info_descr {
     (($($e:expr),+), $t:tt) => {
-        eprint!("\x1b[91m→\x1b[0m  ");
+        eprint!("\x1b[94m→\x1b[0m  ");
         eprintln!($($e),*);
-        private_err_descr!($t);
+        private_info_descr!($t);
     };
     (($($e:expr),+)) => {
-        eprint!("\x1b[91m→\x1b[0m  ");
+        eprint!("\x1b[94m→\x1b[0m  ");
         eprintln!($($e),*);
     };
 }
 
-pub(crate) use {exit_err, private_err_descr};
+pub(crate) use {print_info, private_info_descr};
+
+/// Prints the error in the same format as `print_info`, in red
+pub fn print_error(error: &WormodError) {
+    let message = error.message();
+    eprintln!("\x1b[91mError:\x1b[0m");
+    for line in std::iter::once(&message.summary).chain(message.details.iter()) {
+        eprint!("\x1b[91m→\x1b[0m  ");
+        eprintln!("{}", line);
+    }
+}
@@ -0,0 +1,87 @@
+use super::decoder;
+use super::decoder::LineDecoder;
+use super::dedup;
+use super::entry::Entry;
+use super::memory;
+use super::reader;
+use crate::cli::CheckParams;
+use crate::error::*;
+use crate::params::HashAlgorithm;
+use crate::print::*;
+
+/// Reads the whole input and fails at the first entry not passing the checks
+pub(super) fn check<E: Entry>(params: &CheckParams) -> Result<(), WormodError> {
+    let io_params = params.io_params();
+    memory::set_memory_limit(io_params.memory_limit);
+    let (mut buf_reader, _) = reader::buffered_reader(&io_params)?;
+    let mut decoder = LineDecoder::new(io_params.invalid_utf8, io_params.decode_hex);
+    // duplicates are adjacent in a sorted wordlist
+    let mut seen = (params.unique && !params.sorted).then(|| dedup::unique_set(HashAlgorithm::Siphash));
+    let min_len = params.min_len.unwrap_or(0);
+    let max_len = params.max_len.unwrap_or(usize::MAX);
+
+    let mut n_line = 0;
+    let mut n_entries = 0;
+    let mut previous : Option<E> = None;
+    let mut buffer = E::default();
+    loop {
+        buffer.clear();
+        if !reader::pipe_read(&mut buf_reader, &mut decoder, &mut buffer)? {
+            // reached EOF
+            break;
+        }
+        n_line += 1;
+
+        buffer.trim_whitespaces();
+        if buffer.is_empty() {
+            continue;
+        }
+        n_entries += 1;
+
+        let length = buffer.length(params.length_unit);
+        if length < min_len || length > max_len {
+            return Err(error!(
+                Check,
+                ("Entry out of the length range at line {}: {:?}", n_line, lossy(&buffer)),
+                ("The entry is {} {} long", length, params.length_unit.name())
+            ));
+        }
+        if let Some(previous) = previous.as_ref() {
+            if buffer < *previous {
+                return Err(error!(
+                    Check,
+                    ("Wordlist is not sorted at line {}", n_line),
+                    ("Entry {:?} comes after {:?}", lossy(&buffer), lossy(previous))
+                ));
+            } else if params.unique && buffer == *previous {
+                return Err(error!(
+                    Check,
+                    ("Duplicate entry at line {}: {:?}", n_line, lossy(&buffer))
+                ));
+            }
+        }
+        if let Some(seen) = seen.as_mut() {
+            if !seen.insert(buffer.as_bytes())? {
+                return Err(error!(
+                    Check,
+                    ("Duplicate entry at line {}: {:?}", n_line, lossy(&buffer))
+                ));
+            }
+        }
+        if params.sorted {
+            previous = Some(std::mem::take(&mut buffer));
+        }
+    }
+
+    decoder::report(io_params.invalid_utf8, decoder.n_invalid());
+    print_info!(
+        ("The wordlist passed all the checks"),
+        ("Entries checked: {}", n_entries)
+    );
+    Ok(())
+}
+
+/// The entry as text, for reporting purposes
+fn lossy<E: Entry>(entry: &E) -> String {
+    String::from_utf8_lossy(entry.as_bytes()).into_owned()
+}
@@ -0,0 +1,259 @@
+use crate::error::*;
+use crate::params::CompressionFormat;
+
+use std::io::Cursor;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+/// The magic numbers of the supported formats
+const MAGIC_NUMBERS : [(&[u8], CompressionFormat); 4] = [
+    (&[0x1F, 0x8B], CompressionFormat::Gzip),
+    (b"BZh", CompressionFormat::Bzip2),
+    (&[0xFD, b'7', b'z', b'X', b'Z', 0x00], CompressionFormat::Xz),
+    (&[0x28, 0xB5, 0x2F, 0xFD], CompressionFormat::Zstd),
+];
+
+/// The longest magic number among the supported formats
+const MAGIC_LEN : usize = 6;
+
+impl CompressionFormat {
+    /// Detects the compression format from the first bytes of a stream
+    fn from_magic(magic: &[u8]) -> Self {
+        MAGIC_NUMBERS.iter()
+            .find(|(number, _)| magic.starts_with(number))
+            .map_or(CompressionFormat::None, |(_, format)| *format)
+    }
+
+    /// Detects the compression format from the extension of a file name
+    fn from_extension(extension: &str) -> Self {
+        match extension {
+            "gz" | "gzip" => CompressionFormat::Gzip,
+            "bz2" | "bzip2" => CompressionFormat::Bzip2,
+            "xz" => CompressionFormat::Xz,
+            "zst" | "zstd" => CompressionFormat::Zstd,
+            _ => CompressionFormat::None,
+        }
+    }
+
+    /// Detects the compression format from the extension of a file path
+    pub(crate) fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .map_or(CompressionFormat::None, CompressionFormat::from_extension)
+    }
+}
+
+/// Whether the given first bytes of a stream are too short
+/// to tell whether it begins with a magic number
+fn is_magic_prefix(read: &[u8]) -> bool {
+    MAGIC_NUMBERS.iter().any(|(number, _)| number.len() > read.len() && number.starts_with(read))
+}
+
+/// Wraps the stream in the decoder matching its compression format
+///
+/// The format is detected from the first bytes of the stream. They are read
+/// until they can only be the beginning of plain text, so that reading a pipe
+/// does not wait for more than needed, or after a single read when the input
+/// is interactive. The `fallback` format, as given by the extension of an
+/// input file, is used when the first bytes do not match any magic number.
+/// Returns the wrapped stream and whether it turned out to be compressed
+pub(super) fn decoder(
+    mut stream: Box<dyn Read + Send>,
+    fallback: CompressionFormat,
+    interactive: bool,
+) -> Result<(Box<dyn Read + Send>, bool), WormodError> {
+    let mut magic = [0; MAGIC_LEN];
+    let mut n_read = 0;
+    while n_read < MAGIC_LEN {
+        match stream.read(&mut magic[n_read..]) {
+            Ok(0) => break, // reached EOF
+            Ok(n) => n_read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(error!(
+                    Io,
+                    ("Failed to read: {}", e.to_string())
+                ));
+            },
+        }
+        if interactive || !is_magic_prefix(&magic[..n_read]) {
+            break;
+        }
+    }
+    let format = match CompressionFormat::from_magic(&magic[..n_read]) {
+        CompressionFormat::None => fallback,
+        format => format,
+    };
+    // the bytes read so far are given back in front of the stream
+    let stream = Box::new(Cursor::new(magic).take(n_read as u64).chain(stream));
+    let decoder : Box<dyn Read + Send> = match format {
+        CompressionFormat::None => return Ok((stream, false)),
+        CompressionFormat::Gzip => Box::new(flate2::read::MultiGzDecoder::new(stream)),
+        CompressionFormat::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(stream)),
+        CompressionFormat::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(stream)),
+        CompressionFormat::Zstd => {
+            match zstd::stream::read::Decoder::new(stream) {
+                Ok(decoder) => Box::new(decoder),
+                Err(e) => {
+                    return Err(error!(
+                        Io,
+                        ("Failed to initialize the zstd decoder: {}", e.to_string())
+                    ));
+                },
+            }
+        },
+    };
+    Ok((decoder, true))
+}
+
+/// A compressed output stream
+///
+/// The compressed stream is only complete once [`Encoder::finish`]
+/// has been called
+pub(super) enum Encoder {
+    None(Box<dyn Write>),
+    Gzip(flate2::write::GzEncoder<Box<dyn Write>>),
+    Bzip2(bzip2::write::BzEncoder<Box<dyn Write>>),
+    Xz(xz2::write::XzEncoder<Box<dyn Write>>),
+    Zstd(zstd::stream::write::Encoder<'static, Box<dyn Write>>),
+}
+
+impl Encoder {
+    /// Finalizes the compressed stream and flushes the underlying one
+    pub(super) fn finish(self) -> std::io::Result<()> {
+        let mut stream = match self {
+            Encoder::None(stream) => stream,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Bzip2(encoder) => encoder.finish()?,
+            Encoder::Xz(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        stream.flush()
+    }
+
+    fn stream(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::None(stream) => stream,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Bzip2(encoder) => encoder,
+            Encoder::Xz(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.stream().write_all(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream().flush()
+    }
+}
+
+/// Wraps the stream in the encoder of the given compression format
+pub(super) fn encoder(stream: Box<dyn Write>, format: CompressionFormat) -> Result<Encoder, WormodError> {
+    let encoder = match format {
+        CompressionFormat::None => Encoder::None(stream),
+        CompressionFormat::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(stream, flate2::Compression::default())),
+        CompressionFormat::Bzip2 => Encoder::Bzip2(bzip2::write::BzEncoder::new(stream, bzip2::Compression::default())),
+        CompressionFormat::Xz => Encoder::Xz(xz2::write::XzEncoder::new(stream, 6)),
+        CompressionFormat::Zstd => {
+            match zstd::stream::write::Encoder::new(stream, 0) {
+                Ok(encoder) => Encoder::Zstd(encoder),
+                Err(e) => {
+                    return Err(error!(
+                        Io,
+                        ("Failed to initialize the zstd encoder: {}", e.to_string())
+                    ));
+                },
+            }
+        },
+    };
+    Ok(encoder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::ByteByByte;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    const FORMATS : [CompressionFormat; 4] = [
+        CompressionFormat::Gzip,
+        CompressionFormat::Bzip2,
+        CompressionFormat::Xz,
+        CompressionFormat::Zstd,
+    ];
+
+    /// An output stream whose content remains available once written
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn compressed(content: &[u8], format: CompressionFormat) -> Vec<u8> {
+        let output = SharedBuffer::default();
+        let mut encoder = encoder(Box::new(output.clone()), format).unwrap();
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap();
+        let compressed = output.0.lock().unwrap().clone();
+        compressed
+    }
+
+    fn decompressed(stream: impl Read + Send + 'static, fallback: CompressionFormat) -> (Vec<u8>, bool) {
+        let (mut stream, compressed) = decoder(Box::new(stream), fallback, false).unwrap();
+        let mut content = Vec::new();
+        stream.read_to_end(&mut content).unwrap();
+        (content, compressed)
+    }
+
+    #[test]
+    fn round_trips() {
+        let content = b"password\n123456\nqwerty\n".repeat(100);
+        for format in FORMATS {
+            let compressed = compressed(&content, format);
+            assert!(CompressionFormat::from_magic(&compressed) == format);
+            assert_eq!(decompressed(Cursor::new(compressed.clone()), CompressionFormat::None), (content.clone(), true));
+            assert_eq!(decompressed(ByteByByte(Cursor::new(compressed)), CompressionFormat::None), (content.clone(), true));
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        for content in [&b""[..], b"B", b"BZ", b"BZ\n", b"\x28\xB5\n", b"words\n"] {
+            assert_eq!(decompressed(ByteByByte(Cursor::new(content.to_vec())), CompressionFormat::None), (content.to_vec(), false));
+        }
+    }
+
+    #[test]
+    fn extension_fallback() {
+        let content = b"words\n".to_vec();
+        assert!(CompressionFormat::from_path(Path::new("list.txt.gz")) == CompressionFormat::Gzip);
+        assert!(CompressionFormat::from_path(Path::new("list.zst")) == CompressionFormat::Zstd);
+        assert!(CompressionFormat::from_path(Path::new("list.txt")) == CompressionFormat::None);
+        // the first bytes take precedence over the extension
+        let gzip = compressed(&content, CompressionFormat::Gzip);
+        assert_eq!(decompressed(Cursor::new(gzip), CompressionFormat::Xz), (content.clone(), true));
+        // an input not matching its extension is not passed through
+        let (mut stream, compressed) = decoder(Box::new(Cursor::new(content)), CompressionFormat::Gzip, false).unwrap();
+        assert!(compressed);
+        assert!(stream.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use super::hex;
+use super::memory;
+use crate::error::*;
+use crate::params::InvalidUtf8Policy;
+use crate::print::*;
+
+/// Decodes raw lines into UTF-8 strings, handling invalid
+/// sequences according to the policy
+///
+/// Lines in the `$HEX[...]` notation can optionally be decoded
+/// into their real bytes beforehand
+pub struct LineDecoder {
+    policy: InvalidUtf8Policy,
+    decode_hex: bool,
+    /// Scratch buffer for the raw bytes of a line
+    pub(super) raw: Vec<u8>,
+    n_lines: usize,
+    n_invalid: usize,
+}
+
+impl LineDecoder {
+    pub fn new(policy: InvalidUtf8Policy, decode_hex: bool) -> Self {
+        Self {
+            policy,
+            decode_hex,
+            raw: Vec::new(),
+            n_lines: 0,
+            n_invalid: 0,
+        }
+    }
+
+    /// The number of lines containing invalid UTF-8 sequences
+    pub fn n_invalid(&self) -> usize {
+        self.n_invalid
+    }
+
+    /// Whether lines in the `$HEX[...]` notation are decoded
+    pub(super) fn decodes_hex(&self) -> bool {
+        self.decode_hex
+    }
+
+    /// Whether the valid text contains characters which must be escaped,
+    /// see [`hex::escape`]
+    pub(super) fn escapes(&self, valid: &str) -> bool {
+        matches!(self.policy, InvalidUtf8Policy::Hex) && hex::needs_escaping(valid)
+    }
+
+    /// Decodes the whole buffer, line by line
+    pub fn decode_buffer(&mut self, raw: Vec<u8>) -> Result<String, WormodError> {
+        let raw = match String::from_utf8(raw) {
+            Ok(buffer) if !self.decode_hex && !self.escapes(&buffer) => return Ok(buffer),
+            Ok(buffer) => buffer.into_bytes(),
+            Err(e) => e.into_bytes(),
+        };
+        {
+            let available_memory = memory::available_memory();
+            if !memory::is_memory_enough_with(available_memory, raw.len()) {
+                return Err(error!(
+                    Memory,
+                    ("Not enough memory to decode the input")
+                ));
+            }
+        }
+        let mut buffer = String::with_capacity(raw.len());
+        for line in raw.split(|b| *b == b'\n') {
+            self.decode_line(line, &mut buffer)?;
+            buffer.push('\n');
+        }
+        Ok(buffer)
+    }
+
+    /// Decodes the line held in the scratch buffer
+    ///
+    /// The trailing line feed is not part of the decoded line
+    pub(super) fn decode_raw(&mut self, buffer: &mut String) -> Result<(), WormodError> {
+        let raw = std::mem::take(&mut self.raw);
+        let decoded = self.decode_line(raw.strip_suffix(b"\n").unwrap_or(&raw), buffer);
+        self.raw = raw;
+        self.raw.clear();
+        decoded
+    }
+
+    /// Decodes a single line and appends it to the buffer
+    ///
+    /// Nothing is appended if the line shall be skipped
+    fn decode_line(&mut self, line: &[u8], buffer: &mut String) -> Result<(), WormodError> {
+        self.n_lines += 1;
+        let unhexed = self.unhex(line);
+        let line = unhexed.as_deref().unwrap_or(line);
+        let e = match std::str::from_utf8(line) {
+            Ok(valid) if self.escapes(valid) => {
+                // escaped as well, so that it is written back as it was read
+                hex::escape(line, buffer);
+                return Ok(());
+            },
+            Ok(line) => {
+                buffer.push_str(line);
+                return Ok(());
+            },
+            Err(e) => e,
+        };
+        self.n_invalid += 1;
+        match self.policy {
+            InvalidUtf8Policy::Fail => {
+                return Err(error!(
+                    Encoding,
+                    ("Non-UTF8 character found at line {}", self.n_lines),
+                    ("{}", e.to_string())
+                ));
+            },
+            InvalidUtf8Policy::Skip => (),
+            InvalidUtf8Policy::Replace => buffer.push_str(&String::from_utf8_lossy(line)),
+            InvalidUtf8Policy::Hex => hex::escape(line.strip_suffix(b"\r").unwrap_or(line), buffer),
+        }
+        Ok(())
+    }
+
+    /// Decodes the line if it is in the `$HEX[...]` notation and
+    /// such lines shall be decoded
+    ///
+    /// Lines which would decode to line breaks are kept encoded,
+    /// as they would otherwise be split into several entries
+    pub(super) fn unhex(&self, line: &[u8]) -> Option<Vec<u8>> {
+        self.decode_hex.then(|| hex::decode(line)).flatten()
+            .filter(|unhexed| !unhexed.iter().any(|b| *b == b'\n' || *b == b'\r'))
+    }
+}
+
+/// Reports how many lines contained invalid UTF-8 sequences, if any
+pub fn report(policy: InvalidUtf8Policy, n_invalid: usize) {
+    if n_invalid == 0 {
+        return;
+    }
+    let action = match policy {
+        InvalidUtf8Policy::Fail => return,
+        InvalidUtf8Policy::Skip => "skipped",
+        InvalidUtf8Policy::Replace => "decoded with replacement characters",
+        InvalidUtf8Policy::Hex => "encoded as $HEX[...]",
+    };
+    print_info!(
+        ("Lines containing invalid UTF-8 sequences: {}", n_invalid),
+        ("All of them have been {}", action)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT : &[u8] = b"a\n\xFFb\nc";
+
+    /// Decodes the whole input, which is expected to be valid under the policy
+    fn decoded(policy: InvalidUtf8Policy, decode_hex: bool, raw: &[u8]) -> (String, usize) {
+        let mut decoder = LineDecoder::new(policy, decode_hex);
+        let Ok(buffer) = decoder.decode_buffer(raw.to_vec()) else {
+            panic!("Failed to decode: {:?}", raw);
+        };
+        (buffer, decoder.n_invalid())
+    }
+
+    #[test]
+    fn valid_input_is_untouched() {
+        assert_eq!(decoded(InvalidUtf8Policy::Fail, false, b"a\nb"), ("a\nb".to_owned(), 0));
+    }
+
+    #[test]
+    fn fail_policy() {
+        let mut decoder = LineDecoder::new(InvalidUtf8Policy::Fail, false);
+        assert!(decoder.decode_buffer(INPUT.to_vec()).is_err());
+    }
+
+    #[test]
+    fn skip_policy() {
+        assert_eq!(decoded(InvalidUtf8Policy::Skip, false, INPUT), ("a\n\nc\n".to_owned(), 1));
+    }
+
+    #[test]
+    fn replace_policy() {
+        assert_eq!(decoded(InvalidUtf8Policy::Replace, false, INPUT), ("a\n\u{FFFD}b\nc\n".to_owned(), 1));
+    }
+
+    #[test]
+    fn hex_policy() {
+        let (buffer, n_invalid) = decoded(InvalidUtf8Policy::Hex, false, INPUT);
+        assert_eq!(n_invalid, 1);
+        let lines : Vec<&str> = buffer.lines().collect();
+        assert_eq!(lines[0], "a");
+        assert_eq!(lines[2], "c");
+        // the raw bytes are restored once written
+        let mut raw = Vec::new();
+        assert!(hex::unescape(lines[1], &mut raw));
+        assert_eq!(raw, b"\xFFb");
+        assert_eq!(hex::restored_len(lines[1]), 2);
+    }
+
+    #[test]
+    fn hex_policy_escapes_lookalike_characters() {
+        let (buffer, n_invalid) = decoded(InvalidUtf8Policy::Hex, false, "\u{F7E9}".as_bytes());
+        assert_eq!(n_invalid, 0);
+        let mut raw = Vec::new();
+        assert!(hex::unescape(buffer.trim_end(), &mut raw));
+        assert_eq!(raw, "\u{F7E9}".as_bytes());
+    }
+
+    #[test]
+    fn hex_notation() {
+        let (buffer, _) = decoded(InvalidUtf8Policy::Fail, true, b"$HEX[6162]\n$HEX[616]\n$HEX[zz]\nab");
+        assert_eq!(buffer, "ab\n$HEX[616]\n$HEX[zz]\nab\n");
+        let (buffer, n_invalid) = decoded(InvalidUtf8Policy::Replace, true, b"$HEX[61ff]");
+        assert_eq!((buffer.as_str(), n_invalid), ("a\u{FFFD}\n", 1));
+    }
+
+    #[test]
+    fn hex_notation_with_line_breaks() {
+        // decoding would split the entry in two
+        let (buffer, _) = decoded(InvalidUtf8Policy::Fail, true, b"$HEX[610a62]\n$HEX[610d]\n$HEX[6162]");
+        assert_eq!(buffer, "$HEX[610a62]\n$HEX[610d]\nab\n");
+        let decoder = LineDecoder::new(InvalidUtf8Policy::Fail, true);
+        assert_eq!(decoder.unhex(b"$HEX[610a62]"), None);
+        assert_eq!(decoder.unhex(b"$HEX[6162]"), Some(b"ab".to_vec()));
+    }
+}
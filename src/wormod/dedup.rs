@@ -0,0 +1,234 @@
+use super::memory;
+use crate::error::*;
+use crate::params::HashAlgorithm;
+use crate::print::*;
+
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::BuildHasherDefault;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// The amount of memory that can be taken by new entries
+/// before checking the available memory again
+const CHECK_INTERVAL : usize = 16 * memory::MiB;
+
+/// Keeps track of the entries seen so far
+pub(super) trait Dedup {
+    /// Records the entry
+    ///
+    /// Returns `false` if the entry was already seen
+    fn insert(&mut self, entry: &[u8]) -> Result<bool, WormodError>;
+
+    /// Reports statistics about the entries seen
+    fn report(&self) {}
+}
+
+/// Builds the set of seen entries using the given hashing algorithm
+pub(super) fn unique_set(algorithm: HashAlgorithm) -> Box<dyn Dedup> {
+    match algorithm {
+        HashAlgorithm::Siphash => Box::new(UniqueSet::with_hasher(RandomState::new())),
+        HashAlgorithm::Fnv1a => Box::new(UniqueSet::with_hasher(BuildHasherDefault::<Fnv1a>::default())),
+    }
+}
+
+/// A hash set of entries which accounts for the memory it takes
+///
+/// The available memory is checked every time the set is about to grow
+/// its table, as well as every time the entries stored since the last
+/// check exceed a given amount
+pub(super) struct UniqueSet<S: BuildHasher> {
+    entries: HashSet<Box<[u8]>, S>,
+    unchecked_size: usize,
+}
+
+impl<S: BuildHasher> UniqueSet<S> {
+    pub(super) fn with_hasher(hasher: S) -> Self {
+        Self {
+            entries: HashSet::with_hasher(hasher),
+            unchecked_size: 0,
+        }
+    }
+
+    /// Fails if there is not enough memory left to take the given amount
+    fn reserve_memory(&mut self, take_mem: usize) -> Result<(), WormodError> {
+        let available_memory = memory::available_memory();
+        if !memory::is_memory_enough_with(available_memory, take_mem) {
+            return Err(error!(
+                Memory,
+                ("Not enough memory to complete the operation(s)")
+            ));
+        }
+        self.unchecked_size = 0;
+        Ok(())
+    }
+}
+
+impl<S: BuildHasher> Dedup for UniqueSet<S> {
+    fn insert(&mut self, entry: &[u8]) -> Result<bool, WormodError> {
+        if self.entries.contains(entry) {
+            return Ok(false);
+        }
+        let entry_size = entry.len() + std::mem::size_of::<Box<[u8]>>();
+        if self.entries.len() == self.entries.capacity() {
+            // the table will be reallocated with twice the capacity
+            let table_size = self.entries.capacity().max(1) * 2 * (std::mem::size_of::<Box<[u8]>>() + 1);
+            self.reserve_memory(table_size + self.unchecked_size + entry_size)?;
+        } else if self.unchecked_size + entry_size >= CHECK_INTERVAL {
+            self.reserve_memory(self.unchecked_size + entry_size)?;
+        }
+        self.unchecked_size += entry.len();
+        Ok(self.entries.insert(entry.into()))
+    }
+}
+
+/// A Bloom filter of entries, using a fixed amount of memory
+///
+/// Entries that were never seen may be mistakenly reported as already
+/// seen, with a probability that grows with the number of entries
+pub(super) struct BloomFilter {
+    bits: Vec<u64>,
+    n_bits: u64,
+    n_hashes: u64,
+    n_entries: u64,
+}
+
+impl BloomFilter {
+    /// Builds a filter able to store the given number of entries
+    /// without exceeding the given false-positive rate
+    ///
+    /// Fails if the available memory is not enough to store the filter
+    pub(super) fn new(capacity: usize, error_rate: f64) -> Result<Self, WormodError> {
+        let ln2 = std::f64::consts::LN_2;
+        let n_bits = (-(capacity as f64) * error_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let n_hashes = ((n_bits as f64 / capacity as f64) * ln2).round().max(1.0) as u64;
+        let n_words = n_bits.div_ceil(64) as usize;
+        let filter_size = n_words.saturating_mul(std::mem::size_of::<u64>());
+        let available_memory = memory::available_memory();
+        if !memory::is_memory_enough_with(available_memory, filter_size) {
+            return Err(error!(
+                Memory,
+                ("Not enough memory to store the filter: {:.4} MiB", filter_size as f64 / memory::MiB as f64),
+                ("Consider lowering --approx-capacity or raising --approx-error-rate")
+            ));
+        }
+        Ok(Self {
+            bits: vec![0; n_words],
+            n_bits,
+            n_hashes,
+            n_entries: 0,
+        })
+    }
+
+    /// The probability of a never seen entry being reported as already seen,
+    /// given the number of entries inserted so far
+    pub(super) fn expected_error_rate(&self) -> f64 {
+        let k = self.n_hashes as f64;
+        let fill = 1.0 - (-k * self.n_entries as f64 / self.n_bits as f64).exp();
+        fill.powf(k)
+    }
+}
+
+impl Dedup for BloomFilter {
+    fn insert(&mut self, entry: &[u8]) -> Result<bool, WormodError> {
+        // double hashing: h1 + i * h2
+        let mut sip = DefaultHasher::new();
+        entry.hash(&mut sip);
+        let h1 = sip.finish();
+        let mut fnv = Fnv1a::default();
+        entry.hash(&mut fnv);
+        let h2 = fnv.finish() | 1;
+        let mut seen = true;
+        for i in 0..self.n_hashes {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.n_bits;
+            let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+            seen &= self.bits[word] & mask != 0;
+            self.bits[word] |= mask;
+        }
+        if !seen {
+            self.n_entries += 1;
+        }
+        Ok(!seen)
+    }
+
+    fn report(&self) {
+        print_info!(
+            ("Approximate deduplication: {} unique entries recorded", self.n_entries),
+            ("Expected false-positive rate: {:.6}%", self.expected_error_rate() * 100.0)
+        );
+    }
+}
+
+/// The 64-bit FNV-1a hashing algorithm
+///
+/// Faster than the default SipHash on short entries, but not resistant
+/// to inputs crafted to produce collisions
+pub(super) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes.iter() {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether each entry is seen for the first time
+    fn first_seen(seen: &mut dyn Dedup, entries: &[String]) -> Vec<bool> {
+        entries.iter().map(|e| seen.insert(e.as_bytes()).unwrap()).collect()
+    }
+
+    #[test]
+    fn keeps_first_occurrences() {
+        let entries : Vec<String> = (0..5000).map(|i| format!("{}", (i * 7919) % 1009)).collect();
+        let mut expected = HashSet::new();
+        let expected : Vec<bool> = entries.iter().map(|e| expected.insert(e.clone())).collect();
+        assert_eq!(first_seen(&mut *unique_set(HashAlgorithm::Siphash), &entries), expected);
+        assert_eq!(first_seen(&mut *unique_set(HashAlgorithm::Fnv1a), &entries), expected);
+    }
+
+    #[test]
+    fn fnv1a_reference_values() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = Fnv1a::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn bloom_filter_error_rate() {
+        let (capacity, error_rate) = (20000, 0.01);
+        let mut filter = BloomFilter::new(capacity, error_rate).unwrap();
+        let entries : Vec<String> = (0..capacity).map(|i| format!("entry{}", i)).collect();
+        let n_seen = first_seen(&mut filter, &entries).iter().filter(|new| !**new).count();
+        assert!(n_seen as f64 <= capacity as f64 * error_rate);
+        assert!(filter.expected_error_rate() <= error_rate * 1.01);
+        // entries already seen are never reported as new
+        assert!(first_seen(&mut filter, &entries).iter().all(|new| !new));
+        // probing new entries records them as well, so only a few are probed
+        let others : Vec<String> = (0..capacity / 10).map(|i| format!("other{}", i)).collect();
+        let n_false_positives = first_seen(&mut filter, &others).iter().filter(|new| !**new).count();
+        assert!(n_false_positives as f64 <= others.len() as f64 * error_rate * 2.0);
+    }
+}
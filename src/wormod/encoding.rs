@@ -0,0 +1,428 @@
+use super::hex;
+use super::memory;
+use super::wordlist::RawBuffer;
+use crate::params::TextEncoding;
+use crate::error::*;
+use crate::params::UnrepresentablePolicy;
+use crate::print::*;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::io::ErrorKind;
+use std::io::Read;
+
+impl TextEncoding {
+    /// The name of the encoding, as used in messages
+    fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Latin1 => "ISO-8859-1",
+            TextEncoding::Windows1252 => "Windows-1252",
+            TextEncoding::Cp1251 => "Windows-1251",
+            TextEncoding::Utf16le => "UTF-16LE",
+            TextEncoding::Utf16be => "UTF-16BE",
+        }
+    }
+
+    /// The counterpart of the encoding in `encoding_rs`
+    ///
+    /// ISO-8859-1 has none, since it is treated as Windows-1252 there
+    fn as_encoding_rs(&self) -> Option<&'static encoding_rs::Encoding> {
+        match self {
+            TextEncoding::Utf8 => Some(encoding_rs::UTF_8),
+            TextEncoding::Latin1 => None,
+            TextEncoding::Windows1252 => Some(encoding_rs::WINDOWS_1252),
+            TextEncoding::Cp1251 => Some(encoding_rs::WINDOWS_1251),
+            TextEncoding::Utf16le => Some(encoding_rs::UTF_16LE),
+            TextEncoding::Utf16be => Some(encoding_rs::UTF_16BE),
+        }
+    }
+}
+
+/// The byte order marks of the supported encodings
+const BYTE_ORDER_MARKS : [&[u8]; 3] = [b"\xEF\xBB\xBF", b"\xFF\xFE", b"\xFE\xFF"];
+
+/// The longest byte order mark among the supported encodings
+const BOM_LEN : usize = 3;
+
+impl TextEncoding {
+    /// The encoding whose byte order mark begins the content, if any,
+    /// along with the length of the mark
+    fn from_bom(content: &[u8]) -> Option<(Self, usize)> {
+        let (encoding, bom_len) = encoding_rs::Encoding::for_bom(content)?;
+        let encoding = if encoding == encoding_rs::UTF_16LE {
+            TextEncoding::Utf16le
+        } else if encoding == encoding_rs::UTF_16BE {
+            TextEncoding::Utf16be
+        } else {
+            TextEncoding::Utf8
+        };
+        Some((encoding, bom_len))
+    }
+}
+
+/// Whether the given first bytes of a stream are too short
+/// to tell whether it begins with a byte order mark
+fn is_bom_prefix(read: &[u8]) -> bool {
+    BYTE_ORDER_MARKS.iter().any(|bom| bom.len() > read.len() && bom.starts_with(read))
+}
+
+/// Wraps the stream in a decoder converting it from the given encoding to UTF-8
+///
+/// A byte order mark at the beginning of the stream is removed and takes
+/// precedence over the given encoding. The first bytes are only read until
+/// they cannot be the beginning of such a mark. Raw UTF-8 streams are left
+/// untouched
+pub(super) fn decoder(mut stream: Box<dyn Read + Send>, encoding: TextEncoding, raw: bool) -> Result<Box<dyn Read + Send>, WormodError> {
+    if raw && encoding == TextEncoding::Utf8 {
+        return Ok(stream);
+    }
+    let mut start = [0; BOM_LEN];
+    let mut n_read = 0;
+    while n_read < BOM_LEN {
+        match stream.read(&mut start[n_read..]) {
+            Ok(0) => break, // reached EOF
+            Ok(n) => n_read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(error!(
+                    Io,
+                    ("Failed to read: {}", e.to_string())
+                ));
+            },
+        }
+        if !is_bom_prefix(&start[..n_read]) {
+            break;
+        }
+    }
+    let (encoding, bom_len) = TextEncoding::from_bom(&start[..n_read]).unwrap_or((encoding, 0));
+    // the bytes read after the mark are given back in front of the stream
+    let stream = Box::new(Cursor::new(start[bom_len..n_read].to_vec()).chain(stream));
+    Ok(match encoding {
+        TextEncoding::Utf8 => stream,
+        _ => Box::new(Transcoder::new(stream, encoding)),
+    })
+}
+
+/// Removes the byte order mark at the beginning of the content,
+/// converting it to UTF-8 if needed
+///
+/// The content is left untouched if there is no such mark
+pub(super) fn strip_bom(buffer: RawBuffer) -> Result<RawBuffer, WormodError> {
+    let Some((encoding, bom_len)) = TextEncoding::from_bom(&buffer) else {
+        return Ok(buffer);
+    };
+    let content = &buffer[bom_len..];
+    let available_memory = memory::available_memory();
+    if !memory::is_memory_enough_with(available_memory, content.len().saturating_mul(2)) {
+        return Err(error!(
+            Memory,
+            ("Not enough memory to convert the input")
+        ));
+    }
+    // rare enough for the content to be copied, rather than split in place
+    let mut converted = Vec::new();
+    match encoding {
+        TextEncoding::Utf8 => converted.extend_from_slice(content),
+        _ => {
+            let mut decoder = encoding.as_encoding_rs().unwrap().new_decoder_without_bom_handling();
+            decode_to_utf8(&mut decoder, content, true, &mut converted);
+        },
+    }
+    Ok(RawBuffer::from(converted))
+}
+
+/// Replaces each malformed sequence of the input
+///
+/// It is never valid UTF-8, so that the lines containing such sequences
+/// are handled according to the invalid UTF-8 policy
+const MALFORMED : u8 = 0xFF;
+
+/// Converts the input to UTF-8 and appends it to the output,
+/// `last` telling whether the input is the end of the stream
+///
+/// Malformed sequences are replaced with [`MALFORMED`]
+fn decode_to_utf8(decoder: &mut encoding_rs::Decoder, mut input: &[u8], last: bool, output: &mut Vec<u8>) {
+    loop {
+        let start = output.len();
+        let max_len = decoder.max_utf8_buffer_length_without_replacement(input.len()).unwrap_or(input.len() * 3 + 16);
+        output.resize(start + max_len, 0);
+        let (result, read, written) = decoder.decode_to_utf8_without_replacement(input, &mut output[start..], last);
+        output.truncate(start + written);
+        input = &input[read..];
+        match result {
+            encoding_rs::DecoderResult::InputEmpty => break,
+            encoding_rs::DecoderResult::OutputFull => continue,
+            encoding_rs::DecoderResult::Malformed(_, _) => output.push(MALFORMED),
+        }
+    }
+}
+
+/// Converts a stream to UTF-8 while reading it
+///
+/// Malformed sequences are replaced with [`MALFORMED`]
+struct Transcoder {
+    stream: Box<dyn Read + Send>,
+    /// The decoder, `None` for ISO-8859-1
+    decoder: Option<encoding_rs::Decoder>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_pos: usize,
+    eof: bool,
+}
+
+impl Transcoder {
+    fn new(stream: Box<dyn Read + Send>, encoding: TextEncoding) -> Self {
+        Self {
+            stream,
+            decoder: encoding.as_encoding_rs().map(|e| e.new_decoder_without_bom_handling()),
+            input: vec![0; memory::IO_BUF_SIZE],
+            output: Vec::new(),
+            output_pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Reads and converts the next chunk of the stream
+    fn fill(&mut self) -> std::io::Result<()> {
+        let n = loop {
+            match self.stream.read(&mut self.input) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        self.eof = n == 0;
+        self.output.clear();
+        self.output_pos = 0;
+        let input = &self.input[..n];
+        match self.decoder.as_mut() {
+            None => self.output.extend_from_slice(encoding_rs::mem::decode_latin1(input).as_bytes()),
+            Some(decoder) => decode_to_utf8(decoder, input, self.eof, &mut self.output),
+        }
+        Ok(())
+    }
+}
+
+impl Read for Transcoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.output_pos == self.output.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.output.len() - self.output_pos);
+        buf[..n].copy_from_slice(&self.output[self.output_pos..self.output_pos + n]);
+        self.output_pos += n;
+        Ok(n)
+    }
+}
+
+/// Converts UTF-8 lines into the output encoding, handling unrepresentable
+/// characters according to the policy
+pub(super) struct LineEncoder {
+    encoding: TextEncoding,
+    policy: UnrepresentablePolicy,
+    /// The byte of each character, for single-byte encodings
+    table: Option<HashMap<char, u8>>,
+    /// The encoded line, line feed included
+    line: Vec<u8>,
+    n_lines: usize,
+    n_unrepresentable: usize,
+}
+
+impl LineEncoder {
+    pub(super) fn new(encoding: TextEncoding, policy: UnrepresentablePolicy) -> Self {
+        let table = match encoding {
+            TextEncoding::Utf8 | TextEncoding::Utf16le | TextEncoding::Utf16be => None,
+            TextEncoding::Latin1 => Some((0..=255u8).map(|b| (char::from(b), b)).collect()),
+            _ => {
+                let single_byte = encoding.as_encoding_rs().unwrap();
+                Some((0..=255u8)
+                    .filter_map(|b| {
+                        let raw = [b];
+                        let (decoded, had_errors) = single_byte.decode_without_bom_handling(&raw);
+                        let c = decoded.chars().next()?;
+                        (!had_errors).then_some((c, b))
+                    })
+                    .collect())
+            },
+        };
+        Self {
+            encoding,
+            policy,
+            table,
+            line: Vec::new(),
+            n_lines: 0,
+            n_unrepresentable: 0,
+        }
+    }
+
+    /// Encodes the entry followed by a line feed
+    ///
+    /// Returns `None` if the entry shall be skipped
+    pub(super) fn encode_line(&mut self, entry: &[u8]) -> Result<Option<&[u8]>, WormodError> {
+        self.n_lines += 1;
+        self.line.clear();
+        let unrepresentable = match std::str::from_utf8(entry) {
+            Ok(text) => self.encode_str(text, false),
+            // raw entries which are not valid UTF-8
+            Err(_) => Some(char::REPLACEMENT_CHARACTER),
+        };
+        if let Some(c) = unrepresentable {
+            self.n_unrepresentable += 1;
+            self.line.clear();
+            match self.policy {
+                UnrepresentablePolicy::Fail => {
+                    return Err(error!(
+                        Encoding,
+                        ("Entry {} is not representable in {}", self.n_lines, self.encoding.name()),
+                        ("Character not representable: {:?}", c)
+                    ));
+                },
+                UnrepresentablePolicy::Skip => return Ok(None),
+                UnrepresentablePolicy::Replace => {
+                    let text = String::from_utf8_lossy(entry);
+                    self.encode_str(&text, true);
+                },
+                UnrepresentablePolicy::Hex => {
+                    let mut text = String::new();
+                    hex::encode(entry, &mut text);
+                    self.encode_str(&text, true);
+                },
+            }
+        }
+        self.encode_str("\n", true);
+        Ok(Some(&self.line))
+    }
+
+    /// Appends the encoded text to the line, replacing unrepresentable
+    /// characters with '?' if requested
+    ///
+    /// Returns the first unrepresentable character, if any
+    fn encode_str(&mut self, text: &str, replace: bool) -> Option<char> {
+        match self.encoding {
+            TextEncoding::Utf8 => self.line.extend_from_slice(text.as_bytes()),
+            TextEncoding::Utf16le => text.encode_utf16().for_each(|u| self.line.extend_from_slice(&u.to_le_bytes())),
+            TextEncoding::Utf16be => text.encode_utf16().for_each(|u| self.line.extend_from_slice(&u.to_be_bytes())),
+            _ => {
+                let table = self.table.as_ref().unwrap();
+                for c in text.chars() {
+                    match table.get(&c) {
+                        Some(b) => self.line.push(*b),
+                        None if replace => self.line.push(b'?'),
+                        None => return Some(c),
+                    }
+                }
+            },
+        }
+        None
+    }
+
+    /// Reports how many entries were not representable, if any
+    pub(super) fn report(&self) {
+        if self.n_unrepresentable == 0 {
+            return;
+        }
+        let action = match self.policy {
+            UnrepresentablePolicy::Fail => return,
+            UnrepresentablePolicy::Skip => "skipped",
+            UnrepresentablePolicy::Replace => "written with replacement characters",
+            UnrepresentablePolicy::Hex => "encoded as $HEX[...]",
+        };
+        print_info!(
+            ("Entries not representable in {}: {}", self.encoding.name(), self.n_unrepresentable),
+            ("All of them have been {}", action)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::ByteByByte;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    fn utf16be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect()
+    }
+
+    /// Reads the whole content through the decoder, a few bytes at a time
+    fn decoded(content: &[u8], encoding: TextEncoding, raw: bool) -> Vec<u8> {
+        let mut stream = decoder(Box::new(ByteByByte(Cursor::new(content.to_vec()))), encoding, raw).unwrap();
+        let mut decoded = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            match stream.read(&mut buf).unwrap() {
+                0 => return decoded,
+                n => decoded.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn converts_to_utf8() {
+        let text = "Ça été\nпароль\n";
+        assert_eq!(decoded(b"\xC7a \xE9t\xE9\n", TextEncoding::Latin1, false), "Ça été\n".as_bytes());
+        assert_eq!(decoded(b"\x80\x9F\n", TextEncoding::Windows1252, false), "€Ÿ\n".as_bytes());
+        assert_eq!(decoded(b"\xEF\xE0\xF0\xEE\xEB\xFC\n", TextEncoding::Cp1251, false), "пароль\n".as_bytes());
+        assert_eq!(decoded(&utf16le(text), TextEncoding::Utf16le, false), text.as_bytes());
+        assert_eq!(decoded(&utf16be(text), TextEncoding::Utf16be, false), text.as_bytes());
+        assert_eq!(decoded(&utf16le("💎\n"), TextEncoding::Utf16le, true), "💎\n".as_bytes());
+    }
+
+    #[test]
+    fn marks_malformed_sequences() {
+        // unpaired surrogate, then a truncated code unit
+        let mut content = utf16le("a");
+        content.extend_from_slice(&[0x00, 0xD8]);
+        content.extend_from_slice(&utf16le("b\nc\n"));
+        content.push(b'd');
+        assert_eq!(decoded(&content, TextEncoding::Utf16le, false), b"a\xFFb\nc\n\xFF");
+        let converted = strip_bom(RawBuffer::from([&[0xFF, 0xFE][..], &content].concat())).unwrap();
+        assert_eq!(&converted[..], b"a\xFFb\nc\n\xFF");
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        let text = "word\n";
+        let with_bom = |bom: &[u8], content: &[u8]| [bom, content].concat();
+        // the mark takes precedence over the given encoding
+        for encoding in [TextEncoding::Utf8, TextEncoding::Latin1, TextEncoding::Utf16be] {
+            assert_eq!(decoded(&with_bom(b"\xEF\xBB\xBF", text.as_bytes()), encoding, false), text.as_bytes());
+            assert_eq!(decoded(&with_bom(b"\xFF\xFE", &utf16le(text)), encoding, false), text.as_bytes());
+            assert_eq!(decoded(&with_bom(b"\xFE\xFF", &utf16be(text)), encoding, false), text.as_bytes());
+        }
+        // raw UTF-8 streams are left untouched
+        assert_eq!(decoded(&with_bom(b"\xEF\xBB\xBF", text.as_bytes()), TextEncoding::Utf8, true), "\u{FEFF}word\n".as_bytes());
+        assert_eq!(decoded(b"\xEF\xBB", TextEncoding::Utf8, false), b"\xEF\xBB");
+    }
+
+    #[test]
+    fn strips_byte_order_marks() {
+        let text = "word\n";
+        let stripped = |content: Vec<u8>| strip_bom(RawBuffer::from(content)).unwrap().to_vec();
+        assert_eq!(stripped(text.as_bytes().to_vec()), text.as_bytes());
+        assert_eq!(stripped([&b"\xEF\xBB\xBF"[..], text.as_bytes()].concat()), text.as_bytes());
+        assert_eq!(stripped([&b"\xFF\xFE"[..], &utf16le(text)].concat()), text.as_bytes());
+        assert_eq!(stripped([&b"\xFE\xFF"[..], &utf16be(text)].concat()), text.as_bytes());
+    }
+
+    #[test]
+    fn unrepresentable_policies() {
+        let encoded = |policy: UnrepresentablePolicy, entry: &str| {
+            let mut encoder = LineEncoder::new(TextEncoding::Latin1, policy);
+            encoder.encode_line(entry.as_bytes()).map(|line| line.map(|l| l.to_vec()))
+        };
+        assert_eq!(encoded(UnrepresentablePolicy::Fail, "été").unwrap(), Some(b"\xE9t\xE9\n".to_vec()));
+        assert!(encoded(UnrepresentablePolicy::Fail, "€uro").is_err());
+        assert_eq!(encoded(UnrepresentablePolicy::Skip, "€uro").unwrap(), None);
+        assert_eq!(encoded(UnrepresentablePolicy::Replace, "€uro").unwrap(), Some(b"?uro\n".to_vec()));
+        assert_eq!(encoded(UnrepresentablePolicy::Hex, "€").unwrap(), Some(b"$HEX[e282ac]\n".to_vec()));
+        let mut encoder = LineEncoder::new(TextEncoding::Utf16le, UnrepresentablePolicy::Fail);
+        assert_eq!(encoder.encode_line("pw".as_bytes()).unwrap(), Some(&utf16le("pw\n")[..]));
+    }
+}
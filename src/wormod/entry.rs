@@ -0,0 +1,226 @@
+use super::decoder::LineDecoder;
+use super::hex;
+use super::rules::Rule;
+use super::wordlist::FromBuffer;
+use super::wordlist::RawBuffer;
+use super::wordlist::Wordlist;
+use crate::error::WormodError;
+use crate::params::LengthUnit;
+
+use std::hash::Hash;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// An entry of the wordlist
+///
+/// Entries are either valid UTF-8 strings or raw sequences of bytes
+pub trait Entry: Clone + Default + Ord + Hash + Send + Sync + 'static {
+    /// Splits the raw content of the whole input into entries
+    fn from_raw_buffer(raw: RawBuffer, decoder: &mut LineDecoder) -> Result<Wordlist<Self>, WormodError>;
+
+    /// Appends the raw line held by the decoder to the entry
+    fn decode_raw(decoder: &mut LineDecoder, buffer: &mut Self) -> Result<(), WormodError>;
+
+    /// Builds an entry from bytes which are known to be valid
+    fn from_valid_bytes(raw: Vec<u8>) -> Self;
+
+    /// The content of the entry
+    fn as_bytes(&self) -> &[u8];
+
+    /// Removes leading and trailing whitespaces
+    fn trim_whitespaces(&mut self);
+
+    /// Removes all the content
+    fn clear(&mut self);
+
+    /// Returns the entry reversed
+    fn reversed(&self) -> Self;
+
+    /// Applies the rule to the entry
+    ///
+    /// Returns `None` if the entry gets rejected by the rule
+    fn apply_rule(&self, rule: &Rule, unit: LengthUnit) -> Option<Self>;
+
+    /// The length of the content of an entry in the given unit
+    fn content_length(content: &[u8], unit: LengthUnit) -> usize;
+
+    /// Reverses the content of an entry in place
+    fn reverse_content(content: &mut [u8]);
+
+    /// The length of the entry in the given unit
+    fn length(&self, unit: LengthUnit) -> usize {
+        Self::content_length(self.as_bytes(), unit)
+    }
+
+    /// The length of the entry in bytes
+    fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    /// Whether the entry is empty
+    fn is_empty(&self) -> bool {
+        self.as_bytes().is_empty()
+    }
+}
+
+impl Entry for String {
+    fn from_raw_buffer(raw: RawBuffer, decoder: &mut LineDecoder) -> Result<Wordlist<Self>, WormodError> {
+        if !decoder.decodes_hex() {
+            if let Some(text) = std::str::from_utf8(&raw).ok().filter(|t| !decoder.escapes(t)) {
+                // already valid, split it in place
+                let start = text.len() - text.trim_start().len();
+                let end = text.trim_end().len().max(start);
+                return Wordlist::split(raw, start, end);
+            }
+        }
+        Wordlist::from_buffer(decoder.decode_buffer(raw.into_vec())?)
+    }
+
+    fn decode_raw(decoder: &mut LineDecoder, buffer: &mut Self) -> Result<(), WormodError> {
+        decoder.decode_raw(buffer)
+    }
+
+    fn from_valid_bytes(raw: Vec<u8>) -> Self {
+        String::from_utf8(raw)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_str().as_bytes()
+    }
+
+    fn trim_whitespaces(&mut self) {
+        *self = self.trim().to_owned();
+    }
+
+    fn clear(&mut self) {
+        String::clear(self);
+    }
+
+    /// Reverses the graphemes, so that combined characters stay valid
+    fn reversed(&self) -> Self {
+        self.graphemes(true).rev().collect()
+    }
+
+    fn apply_rule(&self, rule: &Rule, unit: LengthUnit) -> Option<Self> {
+        rule.apply(self, unit)
+    }
+
+    fn content_length(content: &[u8], unit: LengthUnit) -> usize {
+        unit.measure(&String::from_utf8_lossy(content))
+    }
+
+    /// Reverses the graphemes, so that combined characters stay valid
+    fn reverse_content(content: &mut [u8]) {
+        let reversed : String = String::from_utf8_lossy(content).graphemes(true).rev().collect();
+        content.copy_from_slice(reversed.as_bytes());
+    }
+}
+
+impl Entry for Vec<u8> {
+    fn from_raw_buffer(raw: RawBuffer, decoder: &mut LineDecoder) -> Result<Wordlist<Self>, WormodError> {
+        let mut entries : Wordlist<Self> = Wordlist::from_buffer(raw)?;
+        // decoded entries are always shorter than their notation
+        entries.shrink_entries(|e| decoder.unhex(e));
+        Ok(entries)
+    }
+
+    fn decode_raw(decoder: &mut LineDecoder, buffer: &mut Self) -> Result<(), WormodError> {
+        let line = decoder.raw.strip_suffix(b"\n").unwrap_or(&decoder.raw);
+        match decoder.unhex(line) {
+            Some(unhexed) => buffer.extend_from_slice(&unhexed),
+            None => buffer.extend_from_slice(line),
+        }
+        decoder.raw.clear();
+        Ok(())
+    }
+
+    fn from_valid_bytes(raw: Vec<u8>) -> Self {
+        raw
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_slice()
+    }
+
+    fn trim_whitespaces(&mut self) {
+        *self = self.trim_ascii().to_vec();
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    /// Reverses the bytes, regardless of what they represent
+    fn reversed(&self) -> Self {
+        self.iter().rev().copied().collect()
+    }
+
+    /// Raw entries are always measured in bytes
+    fn apply_rule(&self, rule: &Rule, _: LengthUnit) -> Option<Self> {
+        rule.apply_bytes(self)
+    }
+
+    /// Raw entries are always measured in bytes
+    fn content_length(content: &[u8], _: LengthUnit) -> usize {
+        content.len()
+    }
+
+    /// Reverses the bytes, regardless of what they represent
+    fn reverse_content(content: &mut [u8]) {
+        content.reverse();
+    }
+}
+
+impl LengthUnit {
+    /// Measures the length of the text
+    ///
+    /// Text containing escaped raw bytes is measured in bytes once they
+    /// are restored, like raw entries, see [`hex::escape`]
+    pub(super) fn measure(&self, text: &str) -> usize {
+        if hex::needs_escaping(text) {
+            return hex::restored_len(text);
+        }
+        match self {
+            LengthUnit::Bytes => text.len(),
+            LengthUnit::Chars => text.chars().count(),
+            LengthUnit::Graphemes => text.graphemes(true).count(),
+        }
+    }
+
+    /// The name of the unit, in plural form
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            LengthUnit::Bytes => "bytes",
+            LengthUnit::Chars => "characters",
+            LengthUnit::Graphemes => "graphemes",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_in_units() {
+        let text = "ne\u{301}e\u{301}";
+        assert_eq!(LengthUnit::Bytes.measure(text), 7);
+        assert_eq!(LengthUnit::Chars.measure(text), 5);
+        assert_eq!(LengthUnit::Graphemes.measure(text), 3);
+        assert_eq!(text.to_owned().length(LengthUnit::Chars), 5);
+        // raw entries are always measured in bytes
+        assert_eq!(text.as_bytes().to_vec().length(LengthUnit::Graphemes), 7);
+    }
+
+    #[test]
+    fn measures_escaped_bytes() {
+        let mut escaped = String::new();
+        hex::escape(b"ab\xFF\xC3\xA9", &mut escaped);
+        for unit in [LengthUnit::Bytes, LengthUnit::Chars, LengthUnit::Graphemes] {
+            assert_eq!(escaped.length(unit), 5);
+        }
+        // characters added to the escaped entry keep their length in bytes
+        escaped.push('é');
+        assert_eq!(escaped.length(LengthUnit::Chars), 7);
+    }
+}
@@ -0,0 +1,299 @@
+use super::entry::Entry;
+use super::file;
+use super::memory;
+use super::memory::ChunkBudget;
+use crate::error::*;
+use crate::params::Params;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+
+/// The maximum number of runs merged at once
+const MAX_MERGE_WIDTH : usize = 64;
+
+/// The size of the I/O buffers of each run
+const RUN_BUF_SIZE : usize = memory::MiB;
+
+/// Sorts entries by spilling sorted chunks of them to disk
+///
+/// Entries are kept in memory until the chunk grows bigger than the
+/// memory budget, at which point they get sorted and written to a
+/// temporary file (a run). Once all the entries have been pushed, the
+/// runs are merged together into the output
+pub(super) struct ExternalSorter<E: Entry> {
+    chunk: Vec<E>,
+    chunk_size: usize,
+    budget: ChunkBudget,
+    dedup: bool,
+    tmp_parent: PathBuf,
+    tmp_dir: Option<PathBuf>,
+    runs: Vec<PathBuf>,
+    n_runs: usize,
+}
+
+impl<E: Entry> ExternalSorter<E> {
+    pub(super) fn new(params: &Params, dedup: bool, budget: ChunkBudget) -> Self {
+        Self::in_dir(params.tmp_dir.clone().unwrap_or_else(std::env::temp_dir), dedup, budget)
+    }
+
+    /// Builds a sorter storing its runs inside the given directory
+    pub(super) fn in_dir(tmp_parent: PathBuf, dedup: bool, budget: ChunkBudget) -> Self {
+        Self {
+            chunk: Vec::new(),
+            chunk_size: 0,
+            budget,
+            dedup,
+            tmp_parent,
+            tmp_dir: None,
+            runs: Vec::new(),
+            n_runs: 0,
+        }
+    }
+
+    /// Adds an entry, eventually spilling the current chunk to disk
+    pub(super) fn push(&mut self, entry: E) -> Result<(), WormodError> {
+        self.chunk_size += entry.len() + std::mem::size_of::<E>();
+        self.chunk.push(entry);
+        if self.chunk_size >= self.budget.chunk_size()? {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts all the entries pushed so far and emits them in order
+    pub(super) fn finish(mut self, emit: &mut dyn FnMut(E) -> Result<(), WormodError>) -> Result<(), WormodError> {
+        if self.runs.is_empty() {
+            // everything fits in memory
+            let mut chunk = std::mem::take(&mut self.chunk);
+            chunk.par_sort();
+            if self.dedup {
+                chunk.dedup();
+            }
+            return chunk.into_iter().try_for_each(emit);
+        }
+        if !self.chunk.is_empty() {
+            self.spill()?;
+        }
+        while self.runs.len() > MAX_MERGE_WIDTH {
+            let runs = std::mem::take(&mut self.runs);
+            for group in runs.chunks(MAX_MERGE_WIDTH) {
+                let path = self.next_run_path()?;
+                let mut run_writer = create_run(&path)?;
+                RunMerger::<E>::new(group)?.for_each_entry(self.dedup, &mut |e| write_run_entry(&mut run_writer, &path, e.as_bytes()))?;
+                flush_run(&mut run_writer, &path)?;
+                group.iter().for_each(|p| { let _ = std::fs::remove_file(p); });
+                self.runs.push(path);
+            }
+        }
+        RunMerger::<E>::new(&self.runs)?.for_each_entry(self.dedup, emit)
+    }
+
+    /// Sorts the current chunk and writes it to a new run
+    fn spill(&mut self) -> Result<(), WormodError> {
+        self.chunk.par_sort();
+        if self.dedup {
+            self.chunk.dedup();
+        }
+        let path = self.next_run_path()?;
+        let mut run_writer = create_run(&path)?;
+        for e in self.chunk.iter() {
+            write_run_entry(&mut run_writer, &path, e.as_bytes())?;
+        }
+        flush_run(&mut run_writer, &path)?;
+        self.runs.push(path);
+        self.chunk.clear();
+        self.chunk_size = 0;
+        Ok(())
+    }
+
+    /// Returns the path of a new run inside the temporary directory
+    fn next_run_path(&mut self) -> Result<PathBuf, WormodError> {
+        if self.tmp_dir.is_none() {
+            self.tmp_dir = Some(file::create_temp_dir(&self.tmp_parent)?);
+        }
+        self.n_runs += 1;
+        Ok(self.tmp_dir.as_ref().unwrap().join(format!("run-{}", self.n_runs)))
+    }
+}
+
+impl<E: Entry> Drop for ExternalSorter<E> {
+    fn drop(&mut self) {
+        if let Some(tmp_dir) = self.tmp_dir.as_ref() {
+            file::remove_temp_dir(tmp_dir);
+        }
+    }
+}
+
+/// Merges multiple sorted runs into a single sorted sequence
+struct RunMerger<E: Entry> {
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(E, usize)>>,
+}
+
+impl<E: Entry> RunMerger<E> {
+    fn new(runs: &[PathBuf]) -> Result<Self, WormodError> {
+        let mut merger = Self {
+            readers: runs.iter()
+                .map(|p| file::open_input_file(p).map(|f| BufReader::with_capacity(RUN_BUF_SIZE, f)))
+                .collect::<Result<_, _>>()?,
+            heap: BinaryHeap::with_capacity(runs.len()),
+        };
+        for i in 0..merger.readers.len() {
+            merger.refill(i)?;
+        }
+        Ok(merger)
+    }
+
+    /// Calls `f` on every entry in sorted order, skipping duplicates if requested
+    fn for_each_entry(mut self, dedup: bool, f: &mut dyn FnMut(E) -> Result<(), WormodError>) -> Result<(), WormodError> {
+        let mut last : Option<E> = None;
+        while let Some(Reverse((entry, i))) = self.heap.pop() {
+            self.refill(i)?;
+            if dedup {
+                if last.as_ref() == Some(&entry) {
+                    continue;
+                }
+                last = Some(entry.clone());
+            }
+            f(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next entry of the given run into the heap
+    fn refill(&mut self, i: usize) -> Result<(), WormodError> {
+        let mut line = Vec::new();
+        match self.readers[i].read_until(b'\n', &mut line) {
+            Err(e) => {
+                return Err(error!(
+                    Io,
+                    ("Failed to read temporary file: {}", e.to_string())
+                ));
+            },
+            Ok(0) => (),
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                }
+                unescape_record(&mut line);
+                self.heap.push(Reverse((E::from_valid_bytes(line), i)));
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Creates a new run file
+pub(super) fn create_run(path: &PathBuf) -> Result<BufWriter<File>, WormodError> {
+    Ok(BufWriter::with_capacity(RUN_BUF_SIZE, file::open_output_file(path, false)?))
+}
+
+/// Writes an entry to a run file
+fn write_run_entry(run_writer: &mut BufWriter<File>, path: &PathBuf, entry: &[u8]) -> Result<(), WormodError> {
+    write_escaped(run_writer, entry).and_then(|_| run_writer.write_all(b"\n")).map_err(|e| error!(
+        Io,
+        ("Failed to write temporary file: {:?}", path),
+        ("Reason of the failure: {}", e.to_string())
+    ))
+}
+
+/// Writes the content of an entry so that it fits on a single line
+///
+/// Line breaks and backslashes are escaped with a backslash
+pub(super) fn write_escaped(writer: &mut impl Write, entry: &[u8]) -> std::io::Result<()> {
+    let mut rest = entry;
+    while let Some(i) = rest.iter().position(|b| matches!(b, b'\\' | b'\n' | b'\r')) {
+        let escaped : &[u8] = match rest[i] {
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            _ => b"\\\\",
+        };
+        writer.write_all(&rest[..i])?;
+        writer.write_all(escaped)?;
+        rest = &rest[i + 1..];
+    }
+    writer.write_all(rest)
+}
+
+/// Restores in place the content of an entry written by [`write_escaped`]
+pub(super) fn unescape_record(record: &mut Vec<u8>) {
+    if !record.contains(&b'\\') {
+        return;
+    }
+    let mut unescaped = Vec::with_capacity(record.len());
+    let mut bytes = record.iter().copied().peekable();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        unescaped.push(match bytes.next_if(|c| matches!(c, b'n' | b'r' | b'\\')) {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            _ => b'\\',
+        });
+    }
+    *record = unescaped;
+}
+
+/// Flushes a run file
+pub(super) fn flush_run(run_writer: &mut BufWriter<File>, path: &PathBuf) -> Result<(), WormodError> {
+    run_writer.flush().map_err(|e| error!(
+        Io,
+        ("Failed to write temporary file: {:?}", path),
+        ("Reason of the failure: {}", e.to_string())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries with duplicates
+    fn entries() -> Vec<String> {
+        (0..300).map(|i| format!("{}-{}", i % 7, i % 11)).collect()
+    }
+
+    #[test]
+    fn escaped_records() {
+        let entries = entries().into_iter().map(String::into_bytes);
+        for entry in entries.chain([b"\\".to_vec(), b"\\\\n".to_vec(), b"a\r\nb".to_vec(), Vec::new()]) {
+            let mut record = Vec::new();
+            write_escaped(&mut record, &entry).unwrap();
+            assert!(!record.contains(&b'\n') && !record.contains(&b'\r'));
+            unescape_record(&mut record);
+            assert_eq!(record, entry);
+        }
+    }
+
+    /// Sorts the entries on disk, spilling every entry to its own run
+    fn sorted_on_disk(entries: Vec<String>, dedup: bool) -> Vec<String> {
+        let mut sorter = ExternalSorter::in_dir(std::env::temp_dir(), dedup, ChunkBudget::fixed(1));
+        for entry in entries.into_iter() {
+            sorter.push(entry).unwrap();
+        }
+        let mut sorted = Vec::new();
+        sorter.finish(&mut |e| {
+            sorted.push(e);
+            Ok(())
+        }).unwrap();
+        sorted
+    }
+
+    #[test]
+    fn sorts_on_disk() {
+        let mut expected = entries();
+        expected.sort();
+        assert_eq!(sorted_on_disk(entries(), false), expected);
+        expected.dedup();
+        assert_eq!(sorted_on_disk(entries(), true), expected);
+    }
+}
@@ -1,44 +1,99 @@
-use crate::print::*;
+use crate::error::*;
 
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io::ErrorKind;
 use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
-pub(super) fn open_input_file(path: &PathBuf) -> File {
+pub(super) fn open_input_file(path: &PathBuf) -> Result<File, WormodError> {
     OpenOptions::new()
         .read(true)
         .open(path)
-        .map_err(|e| {
-            exit_err!(
-                ("Failed to open input file: {:?}", path),
-                ("Reason of the failure: {}", e.to_string())
-            );
-        }).unwrap()
+        .map_err(|e| error!(
+            Io,
+            ("Failed to open input file: {:?}", path),
+            ("Reason of the failure: {}", e.to_string())
+        ))
 }
 
-pub(super) fn open_output_file(path: &PathBuf, append_mode: bool) -> File {
+pub(super) fn open_output_file(path: &PathBuf, append_mode: bool) -> Result<File, WormodError> {
     OpenOptions::new()
         .create(true)
         .write(true)
         .append(append_mode)
         .truncate(!append_mode)
         .open(path)
-        .map_err(|e| {
-            exit_err!(
-                ("Failed to open output file: {:?}", path),
-                ("Reason of the failure: {}", e.to_string())
-            );
-        }).unwrap()
+        .map_err(|e| error!(
+            Io,
+            ("Failed to open output file: {:?}", path),
+            ("Reason of the failure: {}", e.to_string())
+        ))
 }
 
-pub(super) fn file_size(file: &File, path: &PathBuf) -> usize {
+pub(super) fn file_size(file: &File, path: &PathBuf) -> Result<usize, WormodError> {
     file.metadata()
-        .map_err(|e| {
-            exit_err!(
-                ("Failed to retrieve file size: {:?}", path),
-                ("Reason of the failure: {}", e.to_string())
-            );
-        }).unwrap()
-        .size() as usize
+        .map_err(|e| error!(
+            Io,
+            ("Failed to retrieve file size: {:?}", path),
+            ("Reason of the failure: {}", e.to_string())
+        ))
+        .map(|m| m.size() as usize)
+}
+
+/// The number of temporary directories created so far by the process
+static N_TEMP_DIRS : AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a new uniquely-named directory inside the given one
+///
+/// Names left over by other processes are skipped
+pub(super) fn create_temp_dir(parent: &Path) -> Result<PathBuf, WormodError> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    loop {
+        let n = N_TEMP_DIRS.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("wormod-{}-{}-{}", std::process::id(), nanos, n));
+        match std::fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(error!(
+                    Io,
+                    ("Failed to create temporary directory: {:?}", path),
+                    ("Reason of the failure: {}", e.to_string())
+                ));
+            },
+        }
+    }
+}
+
+/// Removes a temporary directory and all its content
+///
+/// Failures are ignored, since they do not affect the result
+pub(super) fn remove_temp_dir(path: &PathBuf) {
+    let _ = std::fs::remove_dir_all(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distinct_temp_dirs() {
+        let parent = create_temp_dir(&std::env::temp_dir()).unwrap();
+        let paths : Vec<PathBuf> = std::thread::scope(|scope| {
+            let threads : Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| (0..16).map(|_| create_temp_dir(&parent)).collect::<Vec<_>>()))
+                .collect();
+            threads.into_iter().flat_map(|t| t.join().unwrap()).collect::<Result<_, _>>()
+        }).unwrap();
+        let n_created = std::fs::read_dir(&parent).unwrap().count();
+        remove_temp_dir(&parent);
+        assert_eq!(n_created, paths.len());
+    }
 }
@@ -0,0 +1,59 @@
+use crate::params::MatchMode;
+use crate::error::*;
+use crate::params::Params;
+
+use regex::Regex;
+use regex::bytes;
+
+/// Filters the entries by matching them against the given patterns
+///
+/// Patterns are matched against the raw content of the entries, so that
+/// entries which are not valid UTF-8 can be matched as well
+pub(super) struct PatternFilter {
+    include: Vec<bytes::Regex>,
+    exclude: Vec<bytes::Regex>,
+    mode: MatchMode,
+}
+
+impl PatternFilter {
+    pub(super) fn new(params: &Params) -> Result<Self, WormodError> {
+        Ok(Self {
+            include: to_bytes_patterns(&params.match_patterns, !params.bytes)?,
+            exclude: to_bytes_patterns(&params.exclude_patterns, !params.bytes)?,
+            mode: params.match_mode,
+        })
+    }
+
+    /// Whether the entry shall be kept
+    ///
+    /// The entry is kept if it matches the inclusion patterns and
+    /// does not match the exclusion patterns, where matching means
+    /// matching any or all of them depending on the mode
+    pub(super) fn accepts(&self, entry: &[u8]) -> bool {
+        let matches = |patterns: &[bytes::Regex]| match self.mode {
+            MatchMode::Any => patterns.iter().any(|re| re.is_match(entry)),
+            MatchMode::All => patterns.iter().all(|re| re.is_match(entry)),
+        };
+        (self.include.is_empty() || matches(&self.include))
+            && (self.exclude.is_empty() || !matches(&self.exclude))
+    }
+}
+
+/// Compiles the patterns again, to match raw bytes
+///
+/// Without Unicode support, escape sequences and classes match single bytes
+/// rather than UTF-8 encoded characters
+fn to_bytes_patterns(patterns: &[Regex], unicode: bool) -> Result<Vec<bytes::Regex>, WormodError> {
+    patterns.iter()
+        .map(|re| {
+            bytes::RegexBuilder::new(re.as_str())
+                .unicode(unicode)
+                .build()
+                .map_err(|e| error!(
+                    InvalidOptions,
+                    ("Invalid pattern: {}", re.as_str()),
+                    ("{}", e.to_string())
+                ))
+        })
+        .collect()
+}
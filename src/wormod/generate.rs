@@ -0,0 +1,151 @@
+use super::writer;
+use crate::cli::GenerateParams;
+use crate::error::*;
+
+/// Lowercase letters, `?l`
+const LOWER : &str = "abcdefghijklmnopqrstuvwxyz";
+/// Uppercase letters, `?u`
+const UPPER : &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+/// Digits, `?d`
+const DIGITS : &str = "0123456789";
+/// Printable ASCII symbols and space, `?s`
+const SPECIAL : &str = " !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// A position of a mask
+#[derive(Clone)]
+enum Placeholder {
+    /// One of the given characters
+    Charset(Vec<char>),
+    /// One of the characters of the custom charset with the given index
+    Custom(usize),
+}
+
+/// A custom charset, referenced in masks by its position
+#[derive(Clone)]
+pub struct Charset(pub Vec<char>);
+
+/// A mask describing the candidates to generate
+///
+/// Each position of the mask is either a literal character or a
+/// placeholder for a charset, in the same notation used by hashcat
+#[derive(Clone)]
+pub struct Mask {
+    positions: Vec<Placeholder>,
+}
+
+impl Mask {
+    /// Parses the mask
+    ///
+    /// References to custom charsets are only resolved when generating
+    pub fn parse(mask: &str) -> Result<Self, String> {
+        let mut positions = Vec::new();
+        let mut chars = mask.chars();
+        while let Some(c) = chars.next() {
+            if c != '?' {
+                positions.push(Placeholder::Charset(vec![c]));
+                continue;
+            }
+            let symbol = chars.next().ok_or("incomplete placeholder at the end of the mask")?;
+            let placeholder = match symbol.to_digit(10) {
+                Some(0) => return Err("custom charsets are numbered starting from ?1".to_owned()),
+                Some(n) => Placeholder::Custom(n as usize - 1),
+                None => Placeholder::Charset(builtin_charset(symbol)?.chars().collect()),
+            };
+            positions.push(placeholder);
+        }
+        if positions.is_empty() {
+            return Err("the mask is empty".to_owned());
+        }
+        Ok(Self { positions })
+    }
+
+    /// The charsets of each position, with custom charsets resolved
+    pub fn resolve(&self, custom: &[Charset]) -> Result<Vec<Vec<char>>, WormodError> {
+        self.positions.iter()
+            .map(|placeholder| match placeholder {
+                Placeholder::Charset(charset) => Ok(charset.clone()),
+                Placeholder::Custom(n) => custom.get(*n).map(|c| c.0.clone()).ok_or_else(|| error!(
+                    InvalidOptions,
+                    ("Undefined custom charset in mask: ?{}", n + 1),
+                    ("Only {} custom charset(s) given, see --charset", custom.len())
+                )),
+            })
+            .collect()
+    }
+}
+
+/// Parses a custom charset
+///
+/// The charset may contain the built-in placeholders, which are expanded,
+/// while characters appearing more than once are only kept once
+pub fn parse_charset(charset: &str) -> Result<Charset, String> {
+    let mut expanded = String::new();
+    let mut chars = charset.chars();
+    while let Some(c) = chars.next() {
+        if c == '?' {
+            let symbol = chars.next().ok_or("incomplete placeholder at the end of the charset")?;
+            expanded.push_str(&builtin_charset(symbol)?);
+        } else {
+            expanded.push(c);
+        }
+    }
+    let mut charset : Vec<char> = Vec::new();
+    for c in expanded.chars() {
+        if !charset.contains(&c) {
+            charset.push(c);
+        }
+    }
+    if charset.is_empty() {
+        return Err("the charset is empty".to_owned());
+    }
+    Ok(Charset(charset))
+}
+
+/// The characters of the built-in charset with the given symbol
+fn builtin_charset(symbol: char) -> Result<String, String> {
+    let charset = match symbol {
+        'l' => LOWER.to_owned(),
+        'u' => UPPER.to_owned(),
+        'd' => DIGITS.to_owned(),
+        'h' => format!("{}abcdef", DIGITS),
+        'H' => format!("{}ABCDEF", DIGITS),
+        's' => SPECIAL.to_owned(),
+        'a' => [LOWER, UPPER, DIGITS, SPECIAL].concat(),
+        '?' => "?".to_owned(),
+        _ => return Err(format!("unknown placeholder: ?{}", symbol)),
+    };
+    Ok(charset)
+}
+
+/// The number of candidates produced by the given charsets
+///
+/// Returns `None` if the number does not fit in 64 bits
+pub(crate) fn n_candidates(charsets: &[Vec<char>]) -> Option<u64> {
+    charsets.iter().try_fold(1u64, |n, charset| n.checked_mul(charset.len() as u64))
+}
+
+/// Writes all the candidates produced by the mask
+///
+/// Candidates are generated in order, the last position changing first
+pub(super) fn generate(params: &GenerateParams) -> Result<(), WormodError> {
+    let charsets = params.mask.resolve(&params.charset)?;
+    let mut buf_writer = writer::buffered_writer(&params.io_params())?;
+
+    let mut indices = vec![0; charsets.len()];
+    let mut candidate = String::new();
+    loop {
+        candidate.clear();
+        candidate.extend(indices.iter().zip(charsets.iter()).map(|(i, charset)| charset[*i]));
+        writer::write_entry(&mut buf_writer, candidate.as_bytes())?;
+
+        let Some(p) = (0..charsets.len()).rev().find(|p| indices[*p] + 1 < charsets[*p].len()) else {
+            // all the candidates have been generated
+            break;
+        };
+        indices[p] += 1;
+        indices[p + 1..].fill(0);
+    }
+
+    buf_writer.report();
+    writer::finish(buf_writer)
+}
@@ -0,0 +1,114 @@
+use std::fmt::Write;
+
+/// The prefix of entries encoded in hashcat's hexadecimal notation
+const PREFIX : &[u8] = b"$HEX[";
+
+/// The suffix of entries encoded in hashcat's hexadecimal notation
+const SUFFIX : &[u8] = b"]";
+
+/// Characters delimiting the fields of hashcat's potfiles
+const DELIMITERS : &[u8] = b":";
+
+/// Decodes an entry written in the `$HEX[...]` notation
+///
+/// Returns `None` if the entry is not written in such notation,
+/// or if its content is not made of valid pairs of hexadecimal digits
+pub(super) fn decode(entry: &[u8]) -> Option<Vec<u8>> {
+    let digits = entry.trim_ascii()
+        .strip_prefix(PREFIX)?
+        .strip_suffix(SUFFIX)?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits.chunks_exact(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+/// Encodes the entry in the `$HEX[...]` notation
+pub(super) fn encode(entry: &[u8], buffer: &mut String) {
+    buffer.push_str("$HEX[");
+    entry.iter().for_each(|b| { let _ = write!(buffer, "{:02x}", b); });
+    buffer.push(']');
+}
+
+/// Whether the entry shall be encoded for hashcat to read it back as is
+///
+/// This is the case for entries containing non-printable or non-ASCII
+/// bytes, delimiters, or which already look like encoded entries
+pub(super) fn needs_encoding(entry: &[u8]) -> bool {
+    entry.iter().any(|b| !(b' '..=b'~').contains(b) || DELIMITERS.contains(b))
+        || (entry.starts_with(PREFIX) && entry.ends_with(SUFFIX))
+}
+
+/// The first of the private use characters standing for non-ASCII raw bytes
+const RAW_BYTE_BASE : u32 = 0xF700;
+
+/// Maps a raw byte to the character standing for it
+pub(super) fn byte_to_char(b: u8) -> char {
+    if b.is_ascii() {
+        char::from(b)
+    } else {
+        char::from_u32(RAW_BYTE_BASE + b as u32).unwrap()
+    }
+}
+
+/// Maps a character back to the raw byte it stands for, if any
+pub(super) fn char_to_byte(c: char) -> Option<u8> {
+    match c as u32 {
+        n if n < 0x80 => Some(n as u8),
+        n if (RAW_BYTE_BASE + 0x80..=RAW_BYTE_BASE + 0xFF).contains(&n) => Some((n - RAW_BYTE_BASE) as u8),
+        _ => None,
+    }
+}
+
+/// Whether the character stands for a non-ASCII raw byte
+pub(super) fn is_raw_byte(c: char) -> bool {
+    !c.is_ascii() && char_to_byte(c).is_some()
+}
+
+/// Appends the raw line to the buffer, every non-ASCII byte being
+/// replaced by the character standing for it
+///
+/// This way invalid lines can be manipulated like any other entry,
+/// and be restored by [`unescape`] once written
+pub(super) fn escape(line: &[u8], buffer: &mut String) {
+    buffer.extend(line.iter().map(|b| byte_to_char(*b)));
+}
+
+/// Whether the valid line must be escaped as well, since it contains
+/// characters which would otherwise be mistaken for raw bytes
+pub(super) fn needs_escaping(line: &str) -> bool {
+    line.chars().any(is_raw_byte)
+}
+
+/// Restores the raw bytes of an entry containing escaped ones
+///
+/// Returns `false`, leaving `raw` untouched, if the entry contains none
+pub(super) fn unescape(entry: &str, raw: &mut Vec<u8>) -> bool {
+    if !needs_escaping(entry) {
+        return false;
+    }
+    restore(entry.chars(), raw);
+    true
+}
+
+/// The number of bytes of the entry once its escaped bytes are restored,
+/// see [`restore`]
+pub(super) fn restored_len(entry: &str) -> usize {
+    entry.chars().map(|c| if char_to_byte(c).is_some() { 1 } else { c.len_utf8() }).sum()
+}
+
+/// Appends the raw bytes the characters stand for, or
+/// their UTF-8 bytes for those not standing for any
+pub(super) fn restore(chars: impl Iterator<Item=char>, raw: &mut Vec<u8>) {
+    for c in chars {
+        match char_to_byte(c) {
+            Some(b) => raw.push(b),
+            None => raw.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+}
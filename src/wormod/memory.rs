@@ -1,5 +1,13 @@
-use crate::print::*;
+use crate::error::*;
 
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use sysinfo::System;
 
 /// The size of 1 MiB
@@ -13,11 +21,183 @@ pub(super) const IO_BUF_SIZE : usize = 16 * MiB;
 /// in order to not risk to harm the system
 const MIN_AVL_MEM : usize = 64 * MiB;
 
-/// Retrieves the available memory left on the system
+/// The minimum size of the chunks of entries sorted in memory
+/// when sorting on disk
+const MIN_CHUNK_SIZE : usize = MiB;
+
+/// The root of the cgroup hierarchies
+const CGROUP_ROOT : &str = "/sys/fs/cgroup";
+
+/// The maximum amount of memory that can be allocated, see --memory-limit
+static MEMORY_LIMIT : AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The amount of memory currently allocated through the `TrackingAllocator`
+static ALLOCATED : AtomicUsize = AtomicUsize::new(0);
+
+/// The cgroup of the process, if it has a memory limit
+static CGROUP : OnceLock<Option<Cgroup>> = OnceLock::new();
+
+/// A global allocator keeping track of the memory allocated by the process
+///
+/// Allocations are performed by the system allocator. Unless registered
+/// with `#[global_allocator]`, the memory limit only accounts for the
+/// memory available on the system and to the cgroup
+pub struct TrackingAllocator;
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = std::alloc::System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = std::alloc::System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        std::alloc::System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = std::alloc::System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// The memory limit of a cgroup, along with where to read its usage
+struct Cgroup {
+    /// The lowest limit among the cgroup and its ancestors
+    limit: usize,
+    /// The file reporting the memory used by the cgroup
+    usage: PathBuf,
+    /// The file reporting the memory statistics of the cgroup
+    stat: PathBuf,
+    /// The statistic reporting the page cache which can be reclaimed
+    inactive_file: &'static str,
+}
+
+impl Cgroup {
+    /// Retrieves the memory left to the cgroup
+    ///
+    /// Page cache which can be reclaimed is not accounted as used
+    fn available_memory(&self) -> Option<usize> {
+        let usage = read_number(&self.usage)?;
+        let inactive_file = std::fs::read_to_string(&self.stat).ok()
+            .and_then(|stat| stat.lines().find_map(|line| {
+                let (key, value) = line.split_once(' ')?;
+                (key == self.inactive_file).then(|| value.trim().parse::<usize>().ok()).flatten()
+            }))
+            .unwrap_or(0);
+        Some(self.limit.saturating_sub(usage.saturating_sub(inactive_file)))
+    }
+}
+
+/// Sets the maximum amount of memory that can be allocated
+///
+/// `None` removes the limit
+pub(super) fn set_memory_limit(limit: Option<usize>) {
+    MEMORY_LIMIT.store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Retrieves the available memory left
+///
+/// That is the lowest among the memory available on the system, the memory
+/// left to the cgroup of the process and the memory left within the limit
+/// given with --memory-limit
 pub(super) fn available_memory() -> usize {
     let mut sys = System::new();
     sys.refresh_memory();
-    sys.available_memory() as usize
+    let system = sys.available_memory() as usize;
+    let cgroup = CGROUP.get_or_init(detect_cgroup).as_ref()
+        .and_then(|cgroup| cgroup.available_memory())
+        .unwrap_or(usize::MAX);
+    let budget = MEMORY_LIMIT.load(Ordering::Relaxed)
+        .saturating_sub(ALLOCATED.load(Ordering::Relaxed));
+    system.min(cgroup).min(budget)
+}
+
+/// Detects the cgroup of the process, either v2 or v1
+///
+/// Returns `None` if the process does not belong to a cgroup
+/// with a memory limit
+fn detect_cgroup() -> Option<Cgroup> {
+    let membership = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let root = Path::new(CGROUP_ROOT);
+    cgroup_v2(&membership, root).or_else(|| cgroup_v1(&membership, root))
+}
+
+/// Detects the cgroup of the process in the unified hierarchy
+/// mounted at the given root
+fn cgroup_v2(membership: &str, root: &Path) -> Option<Cgroup> {
+    let path = membership.lines().find_map(|line| line.strip_prefix("0::"))?;
+    let dir = cgroup_dir(root, path, "memory.max")?;
+    Some(Cgroup {
+        limit: lowest_limit(&dir, root, "memory.max")?,
+        usage: dir.join("memory.current"),
+        stat: dir.join("memory.stat"),
+        inactive_file: "inactive_file",
+    })
+}
+
+/// Detects the cgroup of the process in the hierarchy of the memory controller,
+/// among the hierarchies mounted at the given root
+fn cgroup_v1(membership: &str, root: &Path) -> Option<Cgroup> {
+    let path = membership.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':').skip(1);
+        let controllers = fields.next()?;
+        let path = fields.next()?;
+        controllers.split(',').any(|c| c == "memory").then_some(path)
+    })?;
+    let root = root.join("memory");
+    let dir = cgroup_dir(&root, path, "memory.limit_in_bytes")?;
+    Some(Cgroup {
+        limit: lowest_limit(&dir, &root, "memory.limit_in_bytes")?,
+        usage: dir.join("memory.usage_in_bytes"),
+        stat: dir.join("memory.stat"),
+        inactive_file: "total_inactive_file",
+    })
+}
+
+/// The directory of the cgroup with the given path
+///
+/// Within containers the root of the hierarchy is usually the cgroup itself,
+/// in which case the path of the cgroup does not exist under the root
+fn cgroup_dir(root: &Path, path: &str, limit_file: &str) -> Option<PathBuf> {
+    let dir = root.join(path.trim_start_matches('/'));
+    if dir.join(limit_file).exists() {
+        Some(dir)
+    } else if root.join(limit_file).exists() {
+        Some(root.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// The lowest limit among the cgroup and its ancestors, up to the root
+fn lowest_limit(dir: &Path, root: &Path, limit_file: &str) -> Option<usize> {
+    dir.ancestors()
+        .take_while(|d| d.starts_with(root))
+        .filter_map(|d| read_number(&d.join(limit_file)))
+        .min()
+}
+
+/// Reads a number from a file of the cgroup hierarchy
+///
+/// Returns `None` if the file cannot be read or contains 'max'
+fn read_number(path: &Path) -> Option<usize> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
 }
 
 pub(super) fn enough_memory_left() -> bool {
@@ -25,24 +205,140 @@ pub(super) fn enough_memory_left() -> bool {
 }
 
 pub(super) fn is_memory_enough_with(avl_mem: usize, take_mem: usize) -> bool {
-    avl_mem - take_mem >= MIN_AVL_MEM
+    avl_mem.saturating_sub(take_mem) >= MIN_AVL_MEM
 }
 
 /// Returns the size for the I/O buffers
 ///
-/// Calls terminate with a failure code if the available memory
-/// left on the system is too low
-pub(super) fn buffer_size(avl_mem: usize) -> usize {
+/// Fails if the available memory left on the system is too low
+pub(super) fn buffer_size(avl_mem: usize) -> Result<usize, WormodError> {
     if avl_mem < MIN_AVL_MEM {
-        fail_low_memory(avl_mem);
+        return Err(low_memory(avl_mem));
+    }
+    Ok(IO_BUF_SIZE)
+}
+
+/// Returns the maximum amount of memory that a chunk of entries
+/// can take when sorting on disk
+///
+/// The I/O buffers are expected to be already allocated, thus accounted
+/// for by the available memory. Fails if the available memory left on
+/// the system is too low
+pub(super) fn chunk_size(avl_mem: usize) -> Result<usize, WormodError> {
+    let usable_mem = avl_mem.saturating_sub(MIN_AVL_MEM);
+    if usable_mem < MIN_CHUNK_SIZE * 2 {
+        return Err(low_memory(avl_mem));
+    }
+    Ok(usable_mem / 2)
+}
+
+/// The memory shared by the operations which hold chunks of entries
+/// before spilling them to disk
+///
+/// The budget is computed from the memory available when it is first
+/// needed, and split evenly among the operations sharing it
+#[derive(Clone, Default)]
+pub(super) struct ChunkBudget {
+    n_shares: Arc<AtomicUsize>,
+    chunk_size: Arc<OnceLock<usize>>,
+}
+
+impl ChunkBudget {
+    /// Returns a handle to the budget for one more operation
+    pub(super) fn share(&self) -> Self {
+        self.n_shares.fetch_add(1, Ordering::Relaxed);
+        self.clone()
+    }
+
+    /// Builds a budget with a fixed size for the chunks
+    #[cfg(test)]
+    pub(super) fn fixed(chunk_size: usize) -> Self {
+        let budget = Self::default();
+        let _ = budget.chunk_size.set(chunk_size);
+        budget
+    }
+
+    /// Returns the maximum amount of memory that each chunk can take
+    ///
+    /// Fails if the available memory left on the system is too low
+    pub(super) fn chunk_size(&self) -> Result<usize, WormodError> {
+        if let Some(size) = self.chunk_size.get() {
+            return Ok(*size);
+        }
+        let n_shares = self.n_shares.load(Ordering::Relaxed).max(1);
+        let size = chunk_size(available_memory())? / n_shares;
+        Ok(*self.chunk_size.get_or_init(|| size))
     }
-    IO_BUF_SIZE
 }
 
-/// Terminates the process with a failure code
-fn fail_low_memory(avl_mem: usize) -> ! {
+/// The error of the available memory being too low
+fn low_memory(avl_mem: usize) -> WormodError {
     let avl_mib = avl_mem as f64 / 1048576.0;
-    exit_err!(
+    error!(
+        Memory,
         ("Available memory is too low: {:.4} MiB", avl_mib)
-    );
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wormod::testing::TempDir;
+
+    /// Writes the given files of a fake cgroup hierarchy
+    fn hierarchy(files: &[(&str, &str)]) -> TempDir {
+        let tmp_dir = TempDir::new();
+        for (name, content) in files {
+            let path = tmp_dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        tmp_dir
+    }
+
+    #[test]
+    fn reads_cgroup_v2() {
+        let tmp_dir = hierarchy(&[
+            ("memory.max", "max\n"),
+            ("user.slice/memory.max", "1000\n"),
+            ("user.slice/app.scope/memory.max", "max\n"),
+            ("user.slice/app.scope/memory.current", "700\n"),
+            ("user.slice/app.scope/memory.stat", "anon 500\ninactive_file 200\n"),
+        ]);
+        let root = tmp_dir.path();
+        let cgroup = cgroup_v2("0::/user.slice/app.scope\n", root).unwrap();
+        assert_eq!(cgroup.limit, 1000);
+        assert_eq!(cgroup.available_memory(), Some(500));
+        assert!(cgroup_v2("0::/user.slice\n", root).is_some());
+        assert!(cgroup_v2("0::/\n", root).is_none());
+        assert!(cgroup_v2("4:memory:/user.slice\n", root).is_none());
+    }
+
+    #[test]
+    fn reads_cgroup_v2_in_container() {
+        let tmp_dir = hierarchy(&[
+            ("memory.max", "4096\n"),
+            ("memory.current", "5000\n"),
+        ]);
+        let cgroup = cgroup_v2("0::/docker/abc\n", tmp_dir.path()).unwrap();
+        assert_eq!(cgroup.limit, 4096);
+        assert_eq!(cgroup.available_memory(), Some(0));
+    }
+
+    #[test]
+    fn reads_cgroup_v1() {
+        let tmp_dir = hierarchy(&[
+            ("memory/memory.limit_in_bytes", "9223372036854771712\n"),
+            ("memory/app/memory.limit_in_bytes", "2048\n"),
+            ("memory/app/memory.usage_in_bytes", "1024\n"),
+            ("memory/app/memory.stat", "cache 512\ntotal_inactive_file 256\n"),
+        ]);
+        let root = tmp_dir.path();
+        let membership = "12:pids:/other\n4:cpu,memory:/app\n1:name=systemd:/\n";
+        let cgroup = cgroup_v1(membership, root).unwrap();
+        assert_eq!(cgroup.limit, 2048);
+        assert_eq!(cgroup.available_memory(), Some(1280));
+        assert!(cgroup_v2(membership, root).is_none());
+        assert!(cgroup_v1("12:pids:/app\n", root).is_none());
+    }
 }
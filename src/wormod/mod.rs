@@ -1,123 +1,284 @@
+mod check;
+mod compression;
+pub mod decoder;
+mod dedup;
+mod encoding;
+pub mod entry;
+mod external;
 mod file;
+mod filter;
+pub mod generate;
+mod hex;
 mod memory;
-mod reader;
-mod wordlist;
-mod writer;
+mod partition;
+pub mod pipeline;
+pub mod reader;
+pub mod rules;
+mod sets;
+mod stats;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod wordlist;
+pub mod writer;
 
-use reader::Reader;
-use wordlist::*;
+pub use memory::TrackingAllocator;
+
+use decoder::LineDecoder;
+use entry::Entry;
+use pipeline::Pipeline;
+use sets::SortedMerge;
+use wordlist::Wordlist;
 use writer::Writer;
+use crate::cli::Command;
+use crate::error::*;
 use crate::params::Params;
-use crate::print::*;
 
-use unicode_segmentation::UnicodeSegmentation;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
 
-type RunResult = Result<(),Box<dyn std::error::Error>>;
+type RunResult = Result<(),WormodError>;
 
-pub(crate) fn run(params: Params) -> RunResult {
-    let (buf_reader, file_size) = reader::buffered_reader(&params);
-    let buf_writer = writer::buffered_writer(&params);
+/// The maximum number of entries processed at once in pipe mode
+const PIPE_BATCH_SIZE : usize = 4096;
 
-    if params.pipe {
-        pipe_mode(params, buf_reader, buf_writer);
-    } else {
-        stock_mode(params, buf_reader, buf_writer, file_size);
+/// Performs the task of the given command
+pub fn run_command(command: Command) -> RunResult {
+    match command {
+        Command::Transform(params) => run(*params),
+        Command::Generate(params) => generate::generate(&params),
+        Command::Stats(params) if params.input_options.bytes => stats::stats::<Vec<u8>>(&params),
+        Command::Stats(params) => stats::stats::<String>(&params),
+        Command::Merge(params) => run(params.io_params()),
+        Command::Check(params) if params.input_options.bytes => check::check::<Vec<u8>>(&params),
+        Command::Check(params) => check::check::<String>(&params),
     }
-
-    Ok(())
 }
 
-fn stock_mode(
-    params: Params,
-    buf_reader: Reader,
-    buf_writer: Writer,
-    file_size: usize,
-) {
-    let buffer = if params.input.is_some() {
-        reader::read_from_file(buf_reader, file_size)
+/// Reads the input, applies the requested operations and writes the output
+pub fn run(params: Params) -> RunResult {
+    memory::set_memory_limit(params.memory_limit);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(params.threads)
+        .build()
+        .map_err(|e| error!(
+            Io,
+            ("Failed to start {} thread(s)", params.threads),
+            ("{}", e.to_string())
+        ))?;
+    pool.install(|| if params.bytes {
+        run_with::<Vec<u8>>(params)
     } else {
-        reader::read_from_stdin(buf_reader)
-    };
-
-    let mut wordlist = Vec::from_buffer(buffer);
-
-    if params.has_length_range() {
-        let min_len = params.min_len.unwrap_or(0);
-        let max_len = params.max_len.unwrap_or(usize::MAX);
-        wordlist.retain(|s| {
-            let entry_len = s.len();
-            (min_len <= entry_len) & (entry_len <= max_len)
-        })
-    }
+        run_with::<String>(params)
+    })
+}
 
-    if params.reverse {
-        wordlist.iter_mut().for_each(|e| {
-            *e = e.graphemes(true).rev().collect::<String>()
-        });
-    }
+/// Runs the operations treating the entries as the given type
+fn run_with<E: Entry>(params: Params) -> RunResult {
+    let buf_writer = writer::buffered_writer(&params)?;
+    let rules = params.rules.as_ref().map(|path| {
+        rules::load_rules(path, params.rules_syntax, params.rules_section.as_deref())
+    }).transpose()?;
+    let pipeline = Pipeline::<E>::new(&params, rules)?;
 
-    if params.sort && params.unique {
-        wordlist.sort_unstable();
-        wordlist.dedup();
-    } else if params.sort {
-        wordlist.sort_unstable();
-    } else if params.unique {
-        wordlist.dedup_unsorted();
+    let policy = params.invalid_utf8;
+    let max_latency = Duration::from_millis(params.max_latency);
+    match (params.set_operation, params.pipe) {
+        (Some(operation), true) => {
+            let merge = SortedMerge::<E>::new(&params, operation)?;
+            let merge = pipe_mode(merge, SortedMerge::next_entry, max_latency, buf_writer, pipeline)?;
+            decoder::report(policy, merge.n_invalid());
+        },
+        (Some(operation), false) => {
+            let wordlist = if params.sorted_inputs {
+                let mut merge = SortedMerge::<E>::new(&params, operation)?;
+                let mut wordlist = Wordlist::new();
+                let mut buffer = E::default();
+                while merge.next_entry(&mut buffer)? {
+                    wordlist.push(buffer.as_bytes());
+                    buffer.clear();
+                }
+                decoder::report(policy, merge.n_invalid());
+                wordlist
+            } else {
+                sets::combine(&params, operation)?
+            };
+            stock_mode(params, wordlist, buf_writer, pipeline)?;
+        },
+        (None, true) => {
+            let (buf_reader, _) = reader::buffered_reader(&params)?;
+            let decoder = LineDecoder::new(policy, params.decode_hex);
+            let (_, decoder) = pipe_mode((buf_reader, decoder), read_entry, max_latency, buf_writer, pipeline)?;
+            decoder::report(policy, decoder.n_invalid());
+        },
+        (None, false) => {
+            let (buf_reader, file_size) = reader::buffered_reader(&params)?;
+            let mut decoder = LineDecoder::new(policy, params.decode_hex);
+            let unknown_size = !params.input.is_empty() && file_size == 0;
+            if (params.sort || params.unique) && (unknown_size || !reader::fits_in_memory(file_size)) {
+                // possibly too big to be sorted or deduplicated in memory, use the disk if needed
+                (_, decoder) = pipe_mode((buf_reader, decoder), read_entry, max_latency, buf_writer, pipeline)?;
+            } else {
+                let wordlist = match params.input.first() {
+                    Some(in_path) if reader::can_map(&params, file_size) => reader::map_all::<E>(&params, in_path, &mut decoder)?,
+                    _ => reader::read_all::<E>(buf_reader, file_size, &mut decoder)?,
+                };
+                stock_mode(params, wordlist, buf_writer, pipeline)?;
+            }
+            decoder::report(policy, decoder.n_invalid());
+        },
     }
 
+    Ok(())
+}
+
+fn stock_mode<E: Entry>(
+    params: Params,
+    wordlist: Wordlist<E>,
+    mut buf_writer: Writer,
+    mut pipeline: Pipeline<E>,
+) -> RunResult {
+    let wordlist = pipeline.apply_list(wordlist)?;
+
     if params.output.is_some() {
-        writer::write_to_file(buf_writer, wordlist);
+        writer::write_to_file(&mut buf_writer, wordlist)?;
     } else {
-        writer::write_to_stdout(buf_writer, wordlist);
+        writer::write_to_stdout(&mut buf_writer, wordlist)?;
     }
+
+    pipeline.report();
+    buf_writer.report();
+    writer::finish(buf_writer)
 }
 
-fn pipe_mode(
-    params: Params,
-    ref mut buf_reader: Reader,
-    ref mut buf_writer: Writer,
-) {
-    let min_len = params.min_len.unwrap_or(0);
-    let max_len = params.max_len.unwrap_or(usize::MAX);
-
-    let ref mut buffer = String::with_capacity(memory::MiB);
-    let mut unique_entries = Vec::new();
-    loop {
-        reader::pipe_read(buf_reader, buffer);
-
-        *buffer = buffer.trim().to_owned();
-        if buffer.is_empty() {
-            // reached EOF
-            break;
-        }
+/// Reads the next entry of the input, see [`reader::pipe_read`]
+fn read_entry<E: Entry>((buf_reader, decoder): &mut (reader::Reader, LineDecoder), buffer: &mut E) -> Result<bool, WormodError> {
+    reader::pipe_read(buf_reader, decoder, buffer)
+}
 
-        let entry_len = buffer.len();
-        if (entry_len < min_len) | (max_len < entry_len) {
+/// Processes the entries in batches, as soon as they are read
+///
+/// Entries are read from the source by `next_entry` on a thread of its own,
+/// which returns `false` once there are no more entries left. A batch is
+/// processed as soon as it is full or its first entry has waited for
+/// `max_latency`, and the resulting entries are written in order and flushed.
+/// Entries held back by whole-list operations (e.g. sorting) are only written
+/// once the EOF is reached.
+///
+/// The source is given back once all the entries have been written. On
+/// failure, the reading thread is not waited for, since it may be blocked
+/// until the next entry is available: it stops on its own once it tries to
+/// pass that entry on
+fn pipe_mode<E: Entry, S: Send + 'static>(
+    mut source: S,
+    next_entry: fn(&mut S, &mut E) -> Result<bool, WormodError>,
+    max_latency: Duration,
+    mut buf_writer: Writer,
+    mut pipeline: Pipeline<E>,
+) -> Result<S, WormodError> {
+    let (sender, receiver) = mpsc::sync_channel::<Result<E, WormodError>>(PIPE_BATCH_SIZE * 2);
+    let reading = std::thread::spawn(move || {
+        let mut buffer = E::default();
+        loop {
             buffer.clear();
-            continue;
+            let entry = match next_entry(&mut source, &mut buffer) {
+                Ok(false) => break, // reached EOF
+                Ok(true) => {
+                    buffer.trim_whitespaces();
+                    if buffer.is_empty() {
+                        continue;
+                    }
+                    Ok(std::mem::take(&mut buffer))
+                },
+                Err(e) => Err(e),
+            };
+            let failed = entry.is_err();
+            // fails once the receiver is dropped
+            if sender.send(entry).is_err() || failed {
+                break;
+            }
         }
+        source
+    });
 
-        if params.unique {
-            if unique_entries.contains(buffer) {
-                buffer.clear();
-                continue;
+    let mut batch = Vec::with_capacity(PIPE_BATCH_SIZE);
+    let mut failure = None;
+    // wait as long as needed for the first entry of each batch
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + max_latency;
+        let mut next = Some(first);
+        while let Some(entry) = next.take() {
+            match entry {
+                Ok(entry) => batch.push(entry),
+                Err(e) => {
+                    // the entries read before the failure are still written
+                    failure = Some(e);
+                    break;
+                },
             }
-            let buffer_size = buffer.len();
-            let available_memory = memory::available_memory();
-            if !memory::is_memory_enough_with(available_memory, buffer_size) {
-                exit_err!(
-                    ("Not enough memory to complete the operation(s)")
-                );
+            if batch.len() < PIPE_BATCH_SIZE {
+                // none if the batch waited long enough or the EOF was reached
+                next = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok();
             }
-            unique_entries.push(buffer.clone());
         }
+        pipeline.push_batch(std::mem::take(&mut batch), &mut |entry| {
+            if entry.is_empty() {
+                return Ok(());
+            }
+            writer::write_entry(&mut buf_writer, entry.as_bytes())
+        })?;
+        writer::flush(&mut buf_writer)?;
+        if let Some(e) = failure {
+            // the read failure matters more than a failure to finish
+            let _ = writer::finish(buf_writer);
+            return Err(e);
+        }
+    }
+    // the EOF was reached, so the reading thread is done
+    let source = reading.join().map_err(|_| error!(
+        Io,
+        ("Failed to read the input")
+    ))?;
 
-        if params.reverse {
-            *buffer = buffer.graphemes(true).rev().collect::<String>();
+    pipeline.finish(&mut |entry| {
+        if entry.is_empty() {
+            return Ok(());
         }
+        writer::write_entry(&mut buf_writer, entry.as_bytes())
+    })?;
+    pipeline.report();
+    buf_writer.report();
+    writer::finish(buf_writer)?;
+    Ok(source)
+}
 
-        writer::pipe_write(buf_writer, buffer);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        buffer.clear();
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn pipe_mode_stops_on_write_failure() {
+        let mut params = Params::defaults();
+        params.output = Some("/dev/full".into());
+        // gives one entry, then waits forever for the next one
+        let (_sender, waiting) = mpsc::channel::<()>();
+        let source = (Some("word".to_string()), waiting);
+        let next_entry = |(first, waiting): &mut (Option<String>, mpsc::Receiver<()>), buffer: &mut String| {
+            match first.take() {
+                Some(entry) => *buffer = entry,
+                None => { let _ = waiting.recv(); },
+            }
+            Ok(true)
+        };
+        let (result_sender, result) = mpsc::channel();
+        std::thread::spawn(move || {
+            let buf_writer = writer::buffered_writer(&params).unwrap();
+            let pipeline = Pipeline::<String>::new(&params, None).unwrap();
+            let failure = pipe_mode(source, next_entry, Duration::ZERO, buf_writer, pipeline).err();
+            let _ = result_sender.send(failure.map(|e| e.exit_code()));
+        });
+        let exit_code = result.recv_timeout(Duration::from_secs(10)).expect("pipe mode is blocked");
+        assert_eq!(exit_code, Some(4));
     }
 }
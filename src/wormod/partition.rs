@@ -0,0 +1,333 @@
+use super::dedup;
+use super::entry::Entry;
+use super::external;
+use super::external::ExternalSorter;
+use super::file;
+use super::memory;
+use super::memory::ChunkBudget;
+use super::wordlist::DedupUnsorted;
+use crate::error::*;
+use crate::params::HashAlgorithm;
+use crate::params::Params;
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The number of buckets the entries are partitioned into
+const N_BUCKETS : usize = 64;
+
+/// The maximum number of times a bucket too big to be stored
+/// in memory gets partitioned again
+const MAX_DEPTH : u32 = 3;
+
+/// The number of digits of the positions prepended to the entries
+/// when restoring their order, enough for any `u64`
+const POSITION_DIGITS : usize = 20;
+
+/// Removes duplicates by partitioning the entries on disk
+///
+/// Entries are kept in memory until they grow bigger than the memory
+/// budget, at which point all of them get partitioned by their hash into
+/// temporary files (buckets), along with their position. Since duplicates
+/// always end up in the same bucket, once all the entries have been pushed
+/// each bucket is deduplicated on its own in memory
+pub(super) struct PartitionedDedup<E: Entry> {
+    chunk: Vec<E>,
+    chunk_size: usize,
+    budget: ChunkBudget,
+    algorithm: HashAlgorithm,
+    keep_last: bool,
+    /// Whether to emit the entries in their original order,
+    /// rather than grouped by bucket
+    keep_order: bool,
+    /// The number of entries pushed to the buckets so far
+    n_entries: u64,
+    buckets: Vec<(PathBuf, BufWriter<File>)>,
+    tmp_parent: PathBuf,
+    tmp_dir: Option<PathBuf>,
+    n_files: usize,
+}
+
+impl<E: Entry> PartitionedDedup<E> {
+    pub(super) fn new(params: &Params, budget: ChunkBudget) -> Self {
+        Self {
+            chunk: Vec::new(),
+            chunk_size: 0,
+            budget,
+            algorithm: params.hasher,
+            keep_last: params.keep_last,
+            keep_order: params.keep_order,
+            n_entries: 0,
+            buckets: Vec::new(),
+            tmp_parent: params.tmp_dir.clone().unwrap_or_else(std::env::temp_dir),
+            tmp_dir: None,
+            n_files: 0,
+        }
+    }
+
+    /// Adds an entry, eventually partitioning all the entries to disk
+    pub(super) fn push(&mut self, entry: E) -> Result<(), WormodError> {
+        if !self.buckets.is_empty() {
+            return self.write_to_bucket(entry.as_bytes());
+        }
+        self.chunk_size += entry.len() + std::mem::size_of::<E>();
+        self.chunk.push(entry);
+        if self.chunk_size >= self.budget.chunk_size()? {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the duplicates among all the entries pushed so far
+    /// and emits the remaining ones
+    pub(super) fn finish(mut self, emit: &mut dyn FnMut(E) -> Result<(), WormodError>) -> Result<(), WormodError> {
+        if self.buckets.is_empty() {
+            // everything fits in memory
+            let mut chunk = std::mem::take(&mut self.chunk);
+            if self.keep_last {
+                chunk.dedup_unsorted_keep_last(self.algorithm)?;
+            } else {
+                chunk.dedup_unsorted(self.algorithm)?;
+            }
+            return chunk.into_iter().try_for_each(emit);
+        }
+        let buckets = close_buckets(std::mem::take(&mut self.buckets))?;
+        let mut sorter = match self.keep_order {
+            // the chunk of this operation is not in use anymore
+            true => Some(ExternalSorter::<Vec<u8>>::in_dir(self.tmp_parent.clone(), false, self.budget.clone())),
+            false => None,
+        };
+        for bucket in buckets.iter() {
+            self.dedup_bucket(bucket, 0, &mut |position, entry| match sorter.as_mut() {
+                Some(sorter) => {
+                    let mut positioned = format!("{:0width$}", position, width=POSITION_DIGITS).into_bytes();
+                    positioned.extend_from_slice(&entry);
+                    sorter.push(positioned)
+                },
+                None => emit(E::from_valid_bytes(entry)),
+            })?;
+        }
+        match sorter {
+            Some(sorter) => sorter.finish(&mut |positioned| {
+                emit(E::from_valid_bytes(positioned[POSITION_DIGITS..].to_vec()))
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Partitions the entries held in memory into new buckets
+    fn spill(&mut self) -> Result<(), WormodError> {
+        self.buckets = self.create_buckets()?;
+        for entry in std::mem::take(&mut self.chunk).iter() {
+            self.write_to_bucket(entry.as_bytes())?;
+        }
+        self.chunk_size = 0;
+        Ok(())
+    }
+
+    /// Writes an entry to its bucket, along with its position
+    fn write_to_bucket(&mut self, entry: &[u8]) -> Result<(), WormodError> {
+        let position = self.n_entries;
+        self.n_entries += 1;
+        let (path, bucket) = &mut self.buckets[bucket_of(entry, 0)];
+        write_record(bucket, path, position, entry)
+    }
+
+    /// Removes the duplicates among the entries of the bucket and passes
+    /// the remaining ones to `out` in the order of their positions
+    ///
+    /// Buckets too big to be stored in memory are partitioned again
+    fn dedup_bucket(
+        &mut self,
+        bucket: &PathBuf,
+        depth: u32,
+        out: &mut dyn FnMut(u64, Vec<u8>) -> Result<(), WormodError>,
+    ) -> Result<(), WormodError> {
+        let bucket_size = file::file_size(&file::open_input_file(bucket)?, bucket)?;
+        if bucket_size > self.budget.chunk_size()? && depth < MAX_DEPTH {
+            let mut partitions = self.create_buckets()?;
+            for_each_record(bucket, &mut |position, entry| {
+                let (path, partition) = &mut partitions[bucket_of(&entry, depth + 1)];
+                write_record(partition, path, position, &entry)
+            })?;
+            let _ = std::fs::remove_file(bucket);
+            for partition in close_buckets(partitions)?.iter() {
+                self.dedup_bucket(partition, depth + 1, out)?;
+            }
+            return Ok(());
+        }
+
+        let available_memory = memory::available_memory();
+        if !memory::is_memory_enough_with(available_memory, bucket_size.saturating_mul(2)) {
+            return Err(error!(
+                Memory,
+                ("Not enough memory to complete the operation(s)")
+            ));
+        }
+        let mut records = Vec::new();
+        for_each_record(bucket, &mut |position, entry| {
+            records.push((position, entry));
+            Ok(())
+        })?;
+        let _ = std::fs::remove_file(bucket);
+
+        let mut seen = dedup::unique_set(self.algorithm);
+        let mut keep = vec![false; records.len()];
+        let order : Box<dyn Iterator<Item=usize>> = match self.keep_last {
+            true => Box::new((0..records.len()).rev()),
+            false => Box::new(0..records.len()),
+        };
+        for i in order {
+            keep[i] = seen.insert(&records[i].1)?;
+        }
+        records.into_iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .try_for_each(|((position, entry), _)| out(position, entry))
+    }
+
+    /// Creates a new set of empty buckets inside the temporary directory
+    fn create_buckets(&mut self) -> Result<Vec<(PathBuf, BufWriter<File>)>, WormodError> {
+        if self.tmp_dir.is_none() {
+            self.tmp_dir = Some(file::create_temp_dir(&self.tmp_parent)?);
+        }
+        (0..N_BUCKETS).map(|_| {
+            self.n_files += 1;
+            let path = self.tmp_dir.as_ref().unwrap().join(format!("bucket-{}", self.n_files));
+            external::create_run(&path).map(|bucket| (path, bucket))
+        }).collect()
+    }
+}
+
+impl<E: Entry> Drop for PartitionedDedup<E> {
+    fn drop(&mut self) {
+        if let Some(tmp_dir) = self.tmp_dir.as_ref() {
+            file::remove_temp_dir(tmp_dir);
+        }
+    }
+}
+
+/// The index of the bucket the entry belongs to
+///
+/// Each level of partitioning hashes the entries differently
+fn bucket_of(entry: &[u8], depth: u32) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    entry.hash(&mut hasher);
+    (hasher.finish() % N_BUCKETS as u64) as usize
+}
+
+/// Flushes the buckets and returns their paths
+fn close_buckets(buckets: Vec<(PathBuf, BufWriter<File>)>) -> Result<Vec<PathBuf>, WormodError> {
+    buckets.into_iter()
+        .map(|(path, mut bucket)| external::flush_run(&mut bucket, &path).map(|_| path))
+        .collect()
+}
+
+/// Writes an entry to a bucket, preceded by its position
+fn write_record(bucket: &mut BufWriter<File>, path: &PathBuf, position: u64, entry: &[u8]) -> Result<(), WormodError> {
+    write!(bucket, "{} ", position)
+        .and_then(|_| external::write_escaped(bucket, entry))
+        .and_then(|_| bucket.write_all(b"\n"))
+        .map_err(|e| error!(
+            Io,
+            ("Failed to write temporary file: {:?}", path),
+            ("Reason of the failure: {}", e.to_string())
+        ))
+}
+
+/// Calls `f` on the position and the content of every entry of a bucket
+fn for_each_record(
+    bucket: &PathBuf,
+    f: &mut dyn FnMut(u64, Vec<u8>) -> Result<(), WormodError>,
+) -> Result<(), WormodError> {
+    let mut reader = BufReader::with_capacity(memory::MiB, file::open_input_file(bucket)?);
+    loop {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Err(e) => {
+                return Err(error!(
+                    Io,
+                    ("Failed to read temporary file: {}", e.to_string())
+                ));
+            },
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                }
+                let separator = line.iter().position(|b| *b == b' ').unwrap_or(line.len());
+                let position = std::str::from_utf8(&line[..separator]).ok()
+                    .and_then(|p| p.parse().ok())
+                    .ok_or_else(|| error!(
+                        Io,
+                        ("Corrupted temporary file: {:?}", bucket)
+                    ))?;
+                let mut entry = line.split_off((separator + 1).min(line.len()));
+                external::unescape_record(&mut entry);
+                f(position, entry)?;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries made of line breaks and backslashes, with duplicates
+    fn entries() -> Vec<Vec<u8>> {
+        (0..2000)
+            .map(|i| format!("{}\n\\{}", (i * 7919) % 613, i % 3).into_bytes())
+            .collect()
+    }
+
+    /// Removes the duplicates on disk, with a budget small enough
+    /// for the entries to be partitioned
+    fn deduplicated_on_disk(keep_order: bool, keep_last: bool) -> Vec<Vec<u8>> {
+        let mut params = Params::defaults();
+        params.keep_order = keep_order;
+        params.keep_last = keep_last;
+        let mut dedup = PartitionedDedup::new(&params, ChunkBudget::fixed(4096));
+        for entry in entries().into_iter() {
+            dedup.push(entry).unwrap();
+        }
+        let mut unique = Vec::new();
+        dedup.finish(&mut |e| {
+            unique.push(e);
+            Ok(())
+        }).unwrap();
+        unique
+    }
+
+    /// Removes the duplicates in memory, keeping the first occurrences
+    fn first_occurrences(entries: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut seen = std::collections::HashSet::new();
+        entries.into_iter().filter(|e| seen.insert(e.clone())).collect()
+    }
+
+    #[test]
+    fn keeps_first_occurrences() {
+        let expected = first_occurrences(entries());
+        assert_eq!(deduplicated_on_disk(true, false), expected);
+        let mut unique = deduplicated_on_disk(false, false);
+        let mut expected = expected;
+        unique.sort();
+        expected.sort();
+        assert_eq!(unique, expected);
+    }
+
+    #[test]
+    fn keeps_last_occurrences() {
+        let mut expected = first_occurrences(entries().into_iter().rev().collect());
+        expected.reverse();
+        assert_eq!(deduplicated_on_disk(true, true), expected);
+    }
+}
@@ -0,0 +1,538 @@
+use super::dedup;
+use super::dedup::Dedup;
+use super::entry::Entry;
+use super::external::ExternalSorter;
+use super::filter::PatternFilter;
+use super::memory::ChunkBudget;
+use super::partition::PartitionedDedup;
+use super::rules::Rule;
+use super::wordlist::*;
+use crate::error::WormodError;
+use crate::params::HashAlgorithm;
+use crate::params::LengthUnit;
+use crate::params::OperationKind;
+use crate::params::Params;
+
+use rayon::prelude::*;
+
+/// An operation manipulating the wordlist
+///
+/// Per-entry operations process each entry as soon as it comes, while
+/// whole-list operations need all the entries before emitting any of them.
+/// Both kinds can run on a whole wordlist stored in memory as well as on a
+/// stream of entries, in which case whole-list operations collect the
+/// entries and only emit them once the stream is finished
+pub trait Operation<E: Entry> {
+    /// Processes a single entry, appending the resulting entries to `out`
+    fn apply_entry(&mut self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError>;
+
+    /// Emits the entries held back so far, once there are no more entries
+    fn finish(&mut self, _emit: &mut Emit<E>) -> Result<(), WormodError> {
+        Ok(())
+    }
+
+    /// Processes the whole wordlist at once
+    ///
+    /// Each entry is built on its own and processed by `apply_entry`
+    fn apply_list(&mut self, wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        let mut out = Wordlist::new();
+        let mut entries = Vec::new();
+        for entry in wordlist.entries() {
+            self.apply_entry(entry, &mut entries)?;
+            entries.drain(..).for_each(|e| out.push(e.as_bytes()));
+        }
+        self.finish(&mut |e| { out.push(e.as_bytes()); Ok(()) })?;
+        Ok(out)
+    }
+
+    /// Reports statistics about the entries processed
+    fn report(&self) {}
+
+    /// The operation as a stateless one, if it processes each entry
+    /// on its own without keeping any state
+    fn as_stateless(&self) -> Option<&dyn StatelessOperation<E>> {
+        None
+    }
+}
+
+/// An operation processing each entry on its own, without keeping any state
+///
+/// Such operations can process multiple entries concurrently
+pub trait StatelessOperation<E: Entry>: Sync {
+    /// Processes a single entry, appending the resulting entries to `out`
+    fn apply(&self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError>;
+}
+
+/// Receives the entries coming out of an operation
+pub type Emit<'a, E> = dyn FnMut(E) -> Result<(), WormodError> + 'a;
+
+/// The sequence of operations to apply, in order
+pub struct Pipeline<E: Entry> {
+    stages: Vec<Box<dyn Operation<E>>>,
+}
+
+impl<E: Entry> Pipeline<E> {
+    /// Builds the pipeline out of the operations requested in the parameters
+    ///
+    /// Sorting and removing duplicates are merged into a single stage
+    /// when they come one right after the other
+    pub fn new(params: &Params, rules: Option<Vec<Rule>>) -> Result<Self, WormodError> {
+        let mut rules = rules;
+        // operations spilling to disk split the memory among themselves
+        let budget = ChunkBudget::default();
+        let mut stages : Vec<Box<dyn Operation<E>>> = Vec::new();
+        let mut kinds = params.operations.iter().peekable();
+        while let Some(kind) = kinds.next() {
+            let sort_unique = kinds.next_if(|next| is_sort_and_unique(*kind, **next, params)).is_some();
+            let stage : Box<dyn Operation<E>> = match kind {
+                _ if sort_unique => Box::new(Sort::new(params, true, budget.share())),
+                OperationKind::Rules => Box::new(ApplyRulesOp {
+                    rules: rules.take().unwrap_or_default(),
+                    unit: params.length_unit,
+                }),
+                OperationKind::Length => Box::new(LengthFilter {
+                    min_len: params.min_len.unwrap_or(0),
+                    max_len: params.max_len.unwrap_or(usize::MAX),
+                    unit: params.length_unit,
+                }),
+                OperationKind::Patterns => Box::new(PatternFilter::new(params)?),
+                OperationKind::Reverse => Box::new(Reverse),
+                OperationKind::Unique if params.pipe => Box::new(Unique::new(params.hasher)),
+                OperationKind::Unique => Box::new(UniqueOnDisk::new(params, budget.share())),
+                OperationKind::UniqueApprox => Box::new(Unique {
+                    seen: Box::new(dedup::BloomFilter::new(params.approx_capacity, params.approx_error_rate)?),
+                    algorithm: None,
+                }),
+                OperationKind::Sort => Box::new(Sort::new(params, false, budget.share())),
+            };
+            stages.push(stage);
+        }
+        Ok(Self { stages })
+    }
+
+    /// Appends an operation at the end of the pipeline
+    ///
+    /// Allows library users to run operations of their own,
+    /// after the ones requested in the parameters
+    pub fn add(&mut self, operation: Box<dyn Operation<E>>) {
+        self.stages.push(operation);
+    }
+
+    /// Applies all the operations to the whole wordlist
+    pub fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        for stage in self.stages.iter_mut() {
+            wordlist = stage.apply_list(wordlist)?;
+        }
+        Ok(wordlist)
+    }
+
+    /// Applies all the operations to a single entry
+    ///
+    /// Resulting entries are passed to `emit`, unless held back
+    /// by a whole-list operation
+    pub fn push(&mut self, entry: E, emit: &mut Emit<E>) -> Result<(), WormodError> {
+        feed(&mut self.stages, entry, emit)
+    }
+
+    /// Applies all the operations to a batch of entries
+    ///
+    /// Consecutive stateless operations process the entries of the batch
+    /// concurrently, while the other operations process them one at a time.
+    /// Resulting entries are passed to `emit` in order, unless held back
+    /// by a whole-list operation
+    pub fn push_batch(&mut self, batch: Vec<E>, emit: &mut Emit<E>) -> Result<(), WormodError> {
+        let mut entries = batch;
+        let mut i = 0;
+        while i < self.stages.len() && !entries.is_empty() {
+            let stateless : Vec<&dyn StatelessOperation<E>> = self.stages[i..].iter()
+                .map_while(|s| s.as_stateless())
+                .collect();
+            if stateless.is_empty() {
+                let mut out = Vec::new();
+                for entry in entries.into_iter() {
+                    self.stages[i].apply_entry(entry, &mut out)?;
+                }
+                entries = out;
+                i += 1;
+            } else {
+                entries = apply_concurrently(&stateless, entries)?;
+                i += stateless.len();
+            }
+        }
+        entries.into_iter().try_for_each(emit)
+    }
+
+    /// Emits the entries held back by whole-list operations
+    ///
+    /// The entries emitted by each stage go through the following ones
+    pub fn finish(&mut self, emit: &mut Emit<E>) -> Result<(), WormodError> {
+        for i in 0..self.stages.len() {
+            let (stage, following) = self.stages[i..].split_first_mut().unwrap();
+            stage.finish(&mut |e| feed(following, e, emit))?;
+        }
+        Ok(())
+    }
+
+    /// Reports statistics about the entries processed by each operation
+    pub fn report(&self) {
+        self.stages.iter().for_each(|s| s.report());
+    }
+}
+
+/// Whether the two operations are sorting and removing duplicates, in any order
+fn is_sort_and_unique(kind: OperationKind, next: OperationKind, params: &Params) -> bool {
+    !params.keep_last && matches!(
+        (kind, next),
+        (OperationKind::Sort, OperationKind::Unique) | (OperationKind::Unique, OperationKind::Sort)
+    )
+}
+
+/// Passes the entry through the given stages
+fn feed<E: Entry>(stages: &mut [Box<dyn Operation<E>>], entry: E, emit: &mut Emit<E>) -> Result<(), WormodError> {
+    let mut entries = vec![entry];
+    for stage in stages.iter_mut() {
+        let mut out = Vec::new();
+        for entry in entries.into_iter() {
+            stage.apply_entry(entry, &mut out)?;
+        }
+        if out.is_empty() {
+            return Ok(());
+        }
+        entries = out;
+    }
+    entries.into_iter().try_for_each(emit)
+}
+
+/// Passes the entries through the given stateless stages, spreading them
+/// across the threads while keeping their order
+fn apply_concurrently<E: Entry>(stages: &[&dyn StatelessOperation<E>], entries: Vec<E>) -> Result<Vec<E>, WormodError> {
+    let chunk_size = entries.len().div_ceil(rayon::current_num_threads()).max(1);
+    let results : Vec<Vec<E>> = entries.into_par_iter()
+        .chunks(chunk_size)
+        .map(|mut entries| {
+            for stage in stages.iter() {
+                let mut out = Vec::with_capacity(entries.len());
+                for entry in entries.into_iter() {
+                    stage.apply(entry, &mut out)?;
+                }
+                entries = out;
+            }
+            Ok(entries)
+        })
+        .collect::<Result<_, WormodError>>()?;
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Replaces each entry with the candidates resulting from the rules
+struct ApplyRulesOp {
+    rules: Vec<Rule>,
+    unit: LengthUnit,
+}
+
+impl<E: Entry> StatelessOperation<E> for ApplyRulesOp {
+    fn apply(&self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        out.extend(self.rules.iter().filter_map(|r| entry.apply_rule(r, self.unit)));
+        Ok(())
+    }
+}
+
+impl<E: Entry> Operation<E> for ApplyRulesOp {
+    fn apply_entry(&mut self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        self.apply(entry, out)
+    }
+
+    fn apply_list(&mut self, wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        wordlist.apply_rules(&self.rules, self.unit)
+    }
+
+    fn as_stateless(&self) -> Option<&dyn StatelessOperation<E>> {
+        Some(self)
+    }
+}
+
+/// Discards the entries whose length is out of range
+struct LengthFilter {
+    min_len: usize,
+    max_len: usize,
+    unit: LengthUnit,
+}
+
+impl<E: Entry> StatelessOperation<E> for LengthFilter {
+    fn apply(&self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        let entry_len = entry.length(self.unit);
+        if (self.min_len <= entry_len) & (entry_len <= self.max_len) {
+            out.push(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<E: Entry> Operation<E> for LengthFilter {
+    fn apply_entry(&mut self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        self.apply(entry, out)
+    }
+
+    fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        wordlist.retain(|e| {
+            let entry_len = E::content_length(e, self.unit);
+            (self.min_len <= entry_len) & (entry_len <= self.max_len)
+        });
+        Ok(wordlist)
+    }
+
+    fn as_stateless(&self) -> Option<&dyn StatelessOperation<E>> {
+        Some(self)
+    }
+}
+
+impl<E: Entry> StatelessOperation<E> for PatternFilter {
+    fn apply(&self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        if self.accepts(entry.as_bytes()) {
+            out.push(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<E: Entry> Operation<E> for PatternFilter {
+    fn apply_entry(&mut self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        self.apply(entry, out)
+    }
+
+    fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        wordlist.retain(|e| self.accepts(e));
+        Ok(wordlist)
+    }
+
+    fn as_stateless(&self) -> Option<&dyn StatelessOperation<E>> {
+        Some(self)
+    }
+}
+
+/// Reverses each entry
+struct Reverse;
+
+impl<E: Entry> StatelessOperation<E> for Reverse {
+    fn apply(&self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        out.push(entry.reversed());
+        Ok(())
+    }
+}
+
+impl<E: Entry> Operation<E> for Reverse {
+    fn apply_entry(&mut self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        self.apply(entry, out)
+    }
+
+    fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        wordlist.reverse_entries();
+        Ok(wordlist)
+    }
+
+    fn as_stateless(&self) -> Option<&dyn StatelessOperation<E>> {
+        Some(self)
+    }
+}
+
+/// Discards the entries which were already seen, keeping the order
+struct Unique {
+    seen: Box<dyn Dedup>,
+    /// The hashing algorithm, `None` if the duplicates are
+    /// only removed approximately
+    algorithm: Option<HashAlgorithm>,
+}
+
+impl Unique {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            seen: dedup::unique_set(algorithm),
+            algorithm: Some(algorithm),
+        }
+    }
+}
+
+impl<E: Entry> Operation<E> for Unique {
+    fn apply_entry(&mut self, entry: E, out: &mut Vec<E>) -> Result<(), WormodError> {
+        if self.seen.insert(entry.as_bytes())? {
+            out.push(entry);
+        }
+        Ok(())
+    }
+
+    fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        match self.algorithm {
+            Some(algorithm) => wordlist.dedup_unsorted(algorithm)?,
+            None => {
+                let mut keep = Vec::with_capacity(wordlist.len());
+                for e in wordlist.iter() {
+                    keep.push(self.seen.insert(e)?);
+                }
+                wordlist.retain_marked(&keep);
+            },
+        }
+        Ok(wordlist)
+    }
+
+    fn report(&self) {
+        self.seen.report();
+    }
+}
+
+/// Discards the entries which were already seen, holding them back
+///
+/// When processing a stream of entries, duplicates are removed on disk
+/// if needed, either keeping the first or the last occurrence of each entry
+struct UniqueOnDisk<E: Entry> {
+    algorithm: HashAlgorithm,
+    keep_last: bool,
+    dedup: Option<PartitionedDedup<E>>,
+}
+
+impl<E: Entry> UniqueOnDisk<E> {
+    fn new(params: &Params, budget: ChunkBudget) -> Self {
+        Self {
+            algorithm: params.hasher,
+            keep_last: params.keep_last,
+            dedup: Some(PartitionedDedup::new(params, budget)),
+        }
+    }
+}
+
+impl<E: Entry> Operation<E> for UniqueOnDisk<E> {
+    fn apply_entry(&mut self, entry: E, _: &mut Vec<E>) -> Result<(), WormodError> {
+        match self.dedup.as_mut() {
+            Some(dedup) => dedup.push(entry),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, emit: &mut Emit<E>) -> Result<(), WormodError> {
+        match self.dedup.take() {
+            Some(dedup) => dedup.finish(emit),
+            None => Ok(()),
+        }
+    }
+
+    fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        if self.keep_last {
+            wordlist.dedup_unsorted_keep_last(self.algorithm)?;
+        } else {
+            wordlist.dedup_unsorted(self.algorithm)?;
+        }
+        Ok(wordlist)
+    }
+}
+
+/// Sorts the entries, optionally removing duplicates
+///
+/// When processing a stream of entries, they are sorted on disk if needed
+struct Sort<E: Entry> {
+    dedup: bool,
+    sorter: Option<ExternalSorter<E>>,
+}
+
+impl<E: Entry> Sort<E> {
+    fn new(params: &Params, dedup: bool, budget: ChunkBudget) -> Self {
+        Self {
+            dedup,
+            sorter: Some(ExternalSorter::new(params, dedup, budget)),
+        }
+    }
+}
+
+impl<E: Entry> Operation<E> for Sort<E> {
+    fn apply_entry(&mut self, entry: E, _: &mut Vec<E>) -> Result<(), WormodError> {
+        match self.sorter.as_mut() {
+            Some(sorter) => sorter.push(entry),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, emit: &mut Emit<E>) -> Result<(), WormodError> {
+        match self.sorter.take() {
+            Some(sorter) => sorter.finish(emit),
+            None => Ok(()),
+        }
+    }
+
+    fn apply_list(&mut self, mut wordlist: Wordlist<E>) -> Result<Wordlist<E>, WormodError> {
+        wordlist.sort();
+        if self.dedup {
+            wordlist.dedup();
+        }
+        Ok(wordlist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(args: &[&str]) -> Pipeline<String> {
+        let params = Params::from_args(["wormod"].iter().chain(args)).unwrap();
+        Pipeline::new(&params, None).unwrap()
+    }
+
+    fn wordlist(entries: &[&str]) -> Wordlist<String> {
+        let mut wordlist = Wordlist::new();
+        entries.iter().for_each(|e| wordlist.push(e.as_bytes()));
+        wordlist
+    }
+
+    /// Appends the entry with its first letter capitalized
+    struct Capitalized;
+
+    impl StatelessOperation<String> for Capitalized {
+        fn apply(&self, entry: String, out: &mut Vec<String>) -> Result<(), WormodError> {
+            let mut chars = entry.chars();
+            let capitalized = chars.next().map(|c| c.to_uppercase().chain(chars).collect());
+            out.push(entry);
+            out.extend(capitalized);
+            Ok(())
+        }
+    }
+
+    impl Operation<String> for Capitalized {
+        fn apply_entry(&mut self, entry: String, out: &mut Vec<String>) -> Result<(), WormodError> {
+            self.apply(entry, out)
+        }
+
+        fn as_stateless(&self) -> Option<&dyn StatelessOperation<String>> {
+            Some(self)
+        }
+    }
+
+    #[test]
+    fn custom_operations() {
+        let mut pipeline = pipeline(&["--reverse"]);
+        pipeline.add(Box::new(Capitalized));
+        let entries : Vec<String> = pipeline.apply_list(wordlist(&["abc", "xyz"])).unwrap().entries().collect();
+        assert_eq!(entries, ["cba", "Cba", "zyx", "Zyx"]);
+        let mut emitted = Vec::new();
+        pipeline.push_batch(vec!["abc".to_string()], &mut |e| { emitted.push(e); Ok(()) }).unwrap();
+        assert_eq!(emitted, ["cba", "Cba"]);
+    }
+
+    #[test]
+    fn same_results_with_any_number_of_threads() {
+        let content : String = (0..20000).map(|i| format!("{}x{}\n", (i * 7919) % 613, i % 3)).collect();
+        let run = |n_threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(n_threads).build().unwrap();
+            pool.install(|| {
+                let wordlist = Wordlist::<String>::split(RawBuffer::from(content.clone().into_bytes()), 0, content.len()).unwrap();
+                let listed : Vec<String> = pipeline(&["--min-len=4", "--reverse", "--sort", "--unique"])
+                    .apply_list(wordlist).unwrap()
+                    .entries().collect();
+                let mut pipeline = pipeline(&["--min-len=4", "--reverse"]);
+                let mut batched = Vec::new();
+                for batch in content.lines().map(String::from).collect::<Vec<_>>().chunks(1000) {
+                    pipeline.push_batch(batch.to_vec(), &mut |e| { batched.push(e); Ok(()) }).unwrap();
+                }
+                (listed, batched)
+            })
+        };
+        let (listed, batched) = run(1);
+        assert!(!listed.is_empty() && listed.len() < batched.len());
+        assert_eq!(run(4), (listed, batched));
+    }
+}
@@ -1,110 +1,223 @@
+use super::compression;
+use super::encoding;
+use super::decoder::LineDecoder;
+use super::entry::Entry;
 use super::file;
 use super::memory;
+use super::wordlist::RawBuffer;
+use super::wordlist::Wordlist;
+use crate::error::*;
+use crate::params::CompressionFormat;
 use crate::params::Params;
-use crate::print::*;
+use crate::params::TextEncoding;
 
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::IsTerminal;
 use std::io::Read;
-use std::str::from_utf8;
+use std::path::PathBuf;
 
-pub(super) type Reader = BufReader<Box<dyn std::io::Read>>;
+pub type Reader = BufReader<Box<dyn std::io::Read + Send>>;
 
-pub(super) fn buffered_reader(params: &Params) -> (Reader, usize) {
-    let available_memory = memory::available_memory();
-    let buffer_size = memory::buffer_size(available_memory);
-    let buf_reader : Reader;
-    let file_size : usize;
-
-    if let Some(in_path) = params.input.as_ref() {
-        params.check_input_path();
-        let in_file = file::open_input_file(in_path);
-        file_size = file::file_size(&in_file, in_path);
-        if file_size == 0 {
-            exit_err!(
-                ("The input file is empty"),
-                ("This is equivalent to a no-op")
-            );
-        } else if params.sort || params.unique {
-            // the whole file must be stored in-memory
-            if file_size >= available_memory - buffer_size * 5 {
-                exit_err!(
-                    ("Available memory is too low"),
-                    ("Not enough memory to perform the requested operation(s)")
-                );
-            }
-        }
-        buf_reader = BufReader::with_capacity(buffer_size, Box::new(in_file));
+/// Returns the reader of the input wordlist along with its size
+///
+/// Compressed inputs are transparently decompressed. The size is 0
+/// when unknown, which is the case for stdin and compressed files
+pub fn buffered_reader(params: &Params) -> Result<(Reader, usize), WormodError> {
+    if let Some(in_path) = params.input.first() {
+        buffered_file_reader(params, in_path)
     } else {
         // reading from standard input
-        file_size = 0;
-        buf_reader = BufReader::with_capacity(buffer_size, Box::new(std::io::stdin()));
+        let available_memory = memory::available_memory();
+        let buffer_size = memory::buffer_size(available_memory)?;
+        let (stdin, _) = compression::decoder(Box::new(std::io::stdin()), CompressionFormat::None, std::io::stdin().is_terminal())?;
+        let stdin = encoding::decoder(stdin, params.input_encoding, params.bytes)?;
+        Ok((BufReader::with_capacity(buffer_size, stdin), 0))
+    }
+}
+
+/// Returns the reader of the given input wordlist along with its size
+///
+/// The size is 0 for compressed files
+pub fn buffered_file_reader(params: &Params, in_path: &PathBuf) -> Result<(Reader, usize), WormodError> {
+    let available_memory = memory::available_memory();
+    let buffer_size = memory::buffer_size(available_memory)?;
+
+    params.check_input_path()?;
+    let in_file = file::open_input_file(in_path)?;
+    let file_size = file::file_size(&in_file, in_path)?;
+    if file_size == 0 {
+        return Err(error!(
+            Path,
+            ("The input file is empty: {:?}", in_path),
+            ("This is equivalent to a no-op")
+        ));
     }
+    let (in_stream, compressed) = compression::decoder(Box::new(in_file), CompressionFormat::from_path(in_path), false)?;
+    let in_stream = encoding::decoder(in_stream, params.input_encoding, params.bytes)?;
+    let buf_reader = BufReader::with_capacity(buffer_size, in_stream);
 
-    (buf_reader, file_size)
+    Ok((buf_reader, if compressed { 0 } else { file_size }))
 }
 
-pub(super) fn read_from_file(mut reader: Reader, file_size: usize) -> String {
+/// Whether a file of the given size can be entirely stored in memory
+/// and manipulated there
+pub fn fits_in_memory(file_size: usize) -> bool {
+    let available_memory = memory::available_memory();
+    file_size < available_memory.saturating_sub(memory::IO_BUF_SIZE * 5)
+}
+
+/// Reads the whole input, whether its size is known or not
+///
+/// The raw content is split into entries, decoding it if needed
+pub fn read_all<E: Entry>(buf_reader: Reader, file_size: usize, decoder: &mut LineDecoder) -> Result<Wordlist<E>, WormodError> {
+    let buffer = if file_size == 0 {
+        read_from_stream(buf_reader)?
+    } else {
+        read_from_file(buf_reader, file_size)?
+    };
+    E::from_raw_buffer(RawBuffer::from(buffer), decoder)
+}
+
+/// Whether an input file of the given size can be mapped in memory
+/// rather than read
+///
+/// Only files which are neither compressed nor in need to be
+/// converted to UTF-8 can be mapped
+pub fn can_map(params: &Params, file_size: usize) -> bool {
+    file_size > 0 && params.input_encoding == TextEncoding::Utf8
+}
+
+/// Maps the whole input file in memory and splits it into entries in place
+///
+/// Entries are only copied if they need to be decoded, or if the
+/// file begins with a byte order mark
+pub fn map_all<E: Entry>(params: &Params, in_path: &PathBuf, decoder: &mut LineDecoder) -> Result<Wordlist<E>, WormodError> {
+    let in_file = file::open_input_file(in_path)?;
+    let buffer = RawBuffer::map_file(&in_file).map_err(|e| error!(
+        Io,
+        ("Failed to map input file: {:?}", in_path),
+        ("Reason of the failure: {}", e.to_string())
+    ))?;
+    let buffer = if params.bytes { buffer } else { encoding::strip_bom(buffer)? };
+    E::from_raw_buffer(buffer, decoder)
+}
+
+fn read_from_file(mut reader: Reader, file_size: usize) -> Result<Vec<u8>, WormodError> {
     let available_memory = memory::available_memory();
     if !memory::is_memory_enough_with(available_memory, file_size) {
-        exit_err!(
+        return Err(error!(
+            Memory,
             ("Not enough memory to read the input file")
-        );
+        ));
     }
-    let mut buffer = String::with_capacity(file_size);
-    if let Err(e) = reader.read_to_string(&mut buffer) {
-        exit_err!(
+    let mut buffer = Vec::with_capacity(file_size);
+    if let Err(e) = reader.read_to_end(&mut buffer) {
+        return Err(error!(
+            Io,
             ("Failed to read input file: {}", e.to_string())
-        );
+        ));
     }
-    buffer
+    Ok(buffer)
 }
 
-pub(super) fn read_from_stdin(mut buf_reader: Reader) -> String {
+/// Reads the whole input without knowing its size in advance
+fn read_from_stream(mut buf_reader: Reader) -> Result<Vec<u8>, WormodError> {
     let check_memory = || {
         if !memory::enough_memory_left() {
-            exit_err!(
+            return Err(error!(
+                Memory,
                 ("Not enough memory to keep reading")
-            );
+            ));
         }
+        Ok(())
     };
-    let mut buffer = String::new();
-    check_memory();
+    let mut buffer = Vec::new();
+    check_memory()?;
     let mut read_buf = vec![0; memory::IO_BUF_SIZE];
     loop {
-        check_memory();
+        check_memory()?;
         match buf_reader.read(read_buf.as_mut_slice()) {
             Err(e) => {
                 match e.kind() {
                     std::io::ErrorKind::Interrupted => continue,
                     _ => {
-                        exit_err!(
+                        return Err(error!(
+                            Io,
                             ("Failed to read: {}", e.to_string())
-                        );
+                        ));
                     }
                 }
             },
             Ok(0) => break, // reached EOF
             Ok(n) => {
                 debug_assert!(n <= memory::IO_BUF_SIZE);
-                match from_utf8(&read_buf[..n]) {
-                    Ok(slice) => buffer.push_str(slice),
-                    Err(e) => {
-                        exit_err!(
-                            ("Non-UTF8 character found: {}", e.to_string())
-                        );
-                    },
-                }
+                buffer.extend_from_slice(&read_buf[..n]);
             },
         }
     }
-    buffer
+    Ok(buffer)
+}
+
+/// Reads one line into the buffer
+///
+/// Returns `false` if the EOF was reached
+pub fn pipe_read<E: Entry>(reader: &mut Reader, decoder: &mut LineDecoder, buffer: &mut E) -> Result<bool, WormodError> {
+    match reader.read_until(b'\n', &mut decoder.raw) {
+        Err(e) => {
+            Err(error!(
+                Io,
+                ("Failed to read: {}", e.to_string())
+            ))
+        },
+        Ok(0) => Ok(false),
+        Ok(_) => {
+            E::decode_raw(decoder, buffer)?;
+            Ok(true)
+        },
+    }
 }
 
-pub(super) fn pipe_read(reader: &mut Reader, buffer: &mut String) {
-    if let Err(e) = reader.read_line(buffer) {
-        exit_err!(
-            ("Failed to read: {}", e.to_string())
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::InvalidUtf8Policy;
+    use crate::wormod::testing::TempDir;
+
+    /// Loads the file both by mapping and by reading it
+    fn load<E: Entry>(params: &Params, in_path: &PathBuf) -> (Vec<E>, Vec<E>) {
+        let new_decoder = || LineDecoder::new(params.invalid_utf8, params.decode_hex);
+        let mapped = map_all::<E>(params, in_path, &mut new_decoder()).unwrap();
+        let (buf_reader, file_size) = buffered_file_reader(params, in_path).unwrap();
+        let read = read_all::<E>(buf_reader, file_size, &mut new_decoder()).unwrap();
+        (mapped.entries().collect(), read.entries().collect())
+    }
+
+    #[test]
+    fn maps_like_reads() {
+        let tmp_dir = TempDir::new();
+        let contents : [&[u8]; 4] = [
+            b"abc\ndef\r\n\nghi",
+            b"\xEF\xBB\xBFbom\nab\xFFc\n$HEX[616263]\n",
+            b"x\xC3\n\xC3\xA9t\xC3\xA9\r\n$HEX[ff]\n$HEX[zz]\n",
+            b"\n\n\r\n",
+        ];
+        for (i, content) in contents.iter().enumerate() {
+            let in_path = tmp_dir.file(&format!("{}.txt", i), content);
+            for policy in [InvalidUtf8Policy::Skip, InvalidUtf8Policy::Replace, InvalidUtf8Policy::Hex] {
+                for decode_hex in [false, true] {
+                    let mut params = Params::defaults();
+                    params.input = vec![in_path.clone()];
+                    params.invalid_utf8 = policy;
+                    params.decode_hex = decode_hex;
+                    assert!(can_map(&params, content.len()));
+                    let (mapped, read) = load::<String>(&params, &in_path);
+                    assert_eq!(mapped, read);
+                    params.bytes = true;
+                    let (mapped, read) = load::<Vec<u8>>(&params, &in_path);
+                    assert_eq!(mapped, read);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,182 @@
+use super::Command;
+use super::Matcher;
+use super::ParseError;
+use super::Rule;
+use super::position;
+
+use std::iter::Enumerate;
+use std::iter::Peekable;
+use std::str::Chars;
+
+type Tokens<'a> = Peekable<Enumerate<Chars<'a>>>;
+
+/// Parses all the rules contained in a rules file
+///
+/// Empty lines and comments are skipped. In case of failure, returns
+/// the line number, the line itself and the reason of the failure
+pub(super) fn parse_rules(content: &str) -> Result<Vec<Rule>, (usize, String, ParseError)> {
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| parse_rule(line).map_err(|e| (n + 1, line.to_owned(), e)))
+        .collect()
+}
+
+/// Parses a single rule in hashcat syntax
+pub(super) fn parse_rule(line: &str) -> Result<Rule, ParseError> {
+    let mut tokens = line.chars().enumerate().peekable();
+    let mut commands = Vec::new();
+    while let Some((i, f)) = tokens.next() {
+        let p = i + 1;
+        let command = match f {
+            ' ' | '\t' => continue,
+            ':' => Command::Noop,
+            'l' => Command::Lowercase,
+            'u' => Command::Uppercase,
+            'c' => Command::Capitalize,
+            'C' => Command::InvertCapitalize,
+            't' => Command::ToggleCase,
+            'T' => Command::ToggleAt(next_position(&mut tokens, p, f)?),
+            'r' => Command::Reverse,
+            'd' => Command::Duplicate,
+            'p' => Command::DuplicateTimes(next_position(&mut tokens, p, f)?),
+            'f' => Command::Reflect,
+            '{' => Command::RotateLeft,
+            '}' => Command::RotateRight,
+            '$' => Command::Append(next_char(&mut tokens, p, f)?),
+            '^' => Command::Prepend(next_char(&mut tokens, p, f)?),
+            '[' => Command::DeleteFirst,
+            ']' => Command::DeleteLast,
+            'D' => Command::DeleteAt(next_position(&mut tokens, p, f)?),
+            'x' => Command::Extract(next_position(&mut tokens, p, f)?, next_position(&mut tokens, p, f)?),
+            'O' => Command::Omit(next_position(&mut tokens, p, f)?, next_position(&mut tokens, p, f)?),
+            'i' => Command::Insert(next_position(&mut tokens, p, f)?, next_char(&mut tokens, p, f)?),
+            'o' => Command::Overwrite(next_position(&mut tokens, p, f)?, next_char(&mut tokens, p, f)?),
+            '\'' => Command::Truncate(next_position(&mut tokens, p, f)?),
+            's' => Command::Replace(Matcher::Char(next_char(&mut tokens, p, f)?), next_char(&mut tokens, p, f)?),
+            '@' => Command::Purge(Matcher::Char(next_char(&mut tokens, p, f)?)),
+            'z' => Command::DuplicateFirst(next_position(&mut tokens, p, f)?),
+            'Z' => Command::DuplicateLast(next_position(&mut tokens, p, f)?),
+            'q' => Command::DuplicateAll,
+            'k' => Command::SwapFront,
+            'K' => Command::SwapBack,
+            '*' => Command::Swap(next_position(&mut tokens, p, f)?, next_position(&mut tokens, p, f)?),
+            'L' => Command::ShiftLeft(next_position(&mut tokens, p, f)?),
+            'R' => Command::ShiftRight(next_position(&mut tokens, p, f)?),
+            '+' => Command::Increment(next_position(&mut tokens, p, f)?),
+            '-' => Command::Decrement(next_position(&mut tokens, p, f)?),
+            '.' => Command::ReplaceNext(next_position(&mut tokens, p, f)?),
+            ',' => Command::ReplacePrev(next_position(&mut tokens, p, f)?),
+            'y' => Command::DuplicateBlockFront(next_position(&mut tokens, p, f)?),
+            'Y' => Command::DuplicateBlockBack(next_position(&mut tokens, p, f)?),
+            'E' => Command::Title,
+            'e' => Command::TitleSeparator(next_char(&mut tokens, p, f)?),
+            '3' => Command::ToggleAfter(next_position(&mut tokens, p, f)?, next_char(&mut tokens, p, f)?),
+            // memory and rejection functions
+            'X' | '4' | '6' | 'M' | '<' | '>' | '!' | '/' | '(' | ')' | '=' | '%' | 'Q' | '_' => {
+                return Err(ParseError::Unsupported(p, f));
+            },
+            _ => {
+                return Err(ParseError::Unknown(p, f));
+            },
+        };
+        commands.push(command);
+    }
+    Ok(Rule { commands })
+}
+
+/// Consumes the next character and returns it as it is
+fn next_char(tokens: &mut Tokens, p: usize, f: char) -> Result<char, ParseError> {
+    tokens.next()
+        .map(|(_, c)| c)
+        .ok_or(ParseError::MissingArgument(p, f))
+}
+
+/// Consumes the next character and returns it as a position
+fn next_position(tokens: &mut Tokens, p: usize, f: char) -> Result<usize, ParseError> {
+    let (i, c) = tokens.next().ok_or(ParseError::MissingArgument(p, f))?;
+    position(c).ok_or(ParseError::InvalidPosition(i + 1, c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::LengthUnit;
+
+    /// Applies a single rule to the word
+    fn apply(rule: &str, word: &str) -> Option<String> {
+        let Ok(parsed) = parse_rule(rule) else {
+            panic!("Failed to parse rule: {}", rule);
+        };
+        parsed.apply(word, LengthUnit::Chars)
+    }
+
+    #[test]
+    fn reference_outputs() {
+        // examples from the documentation of hashcat
+        let cases = [
+            (":", "p@ssW0rd", "p@ssW0rd"),
+            ("l", "p@ssW0rd", "p@ssw0rd"),
+            ("u", "p@ssW0rd", "P@SSW0RD"),
+            ("c", "p@ssW0rd", "P@ssw0rd"),
+            ("C", "p@ssW0rd", "p@SSW0RD"),
+            ("t", "p@ssW0rd", "P@SSw0RD"),
+            ("T3", "p@ssW0rd", "p@sSW0rd"),
+            ("r", "p@ssW0rd", "dr0Wss@p"),
+            ("d", "p@ssW0rd", "p@ssW0rdp@ssW0rd"),
+            ("p2", "p@ssW0rd", "p@ssW0rdp@ssW0rdp@ssW0rd"),
+            ("f", "p@ssW0rd", "p@ssW0rddr0Wss@p"),
+            ("{", "p@ssW0rd", "@ssW0rdp"),
+            ("}", "p@ssW0rd", "dp@ssW0r"),
+            ("$1$2", "p@ssW0rd", "p@ssW0rd12"),
+            ("^2^1", "p@ssW0rd", "12p@ssW0rd"),
+            ("[", "p@ssW0rd", "@ssW0rd"),
+            ("]", "p@ssW0rd", "p@ssW0r"),
+            ("D3", "p@ssW0rd", "p@sW0rd"),
+            ("x04", "p@ssW0rd", "p@ss"),
+            ("O12", "p@ssW0rd", "psW0rd"),
+            ("i4!", "p@ssW0rd", "p@ss!W0rd"),
+            ("o3$", "p@ssW0rd", "p@s$W0rd"),
+            ("'6", "p@ssW0rd", "p@ssW0"),
+            ("ss$", "p@ssW0rd", "p@$$W0rd"),
+            ("@s", "p@ssW0rd", "p@W0rd"),
+            ("z2", "p@ssW0rd", "ppp@ssW0rd"),
+            ("Z2", "p@ssW0rd", "p@ssW0rddd"),
+            ("q", "p@ssW0rd", "pp@@ssssWW00rrdd"),
+            ("k", "p@ssW0rd", "@pssW0rd"),
+            ("K", "p@ssW0rd", "p@ssW0dr"),
+            ("*34", "p@ssW0rd", "p@sWs0rd"),
+            ("R2", "p@ssW0rd", "p@9sW0rd"),
+            ("+2", "p@ssW0rd", "p@tsW0rd"),
+            ("-1", "p@ssW0rd", "p?ssW0rd"),
+            (".1", "p@ssW0rd", "psssW0rd"),
+            (",1", "p@ssW0rd", "ppssW0rd"),
+            ("y2", "p@ssW0rd", "p@p@ssW0rd"),
+            ("Y2", "p@ssW0rd", "p@ssW0rdrd"),
+            ("E", "p@ssW0rd w0rld", "P@ssw0rd W0rld"),
+            ("e-", "pass-word", "Pass-Word"),
+            ("30-", "pass-word", "pass-Word"),
+            ("c $1 $!", "password", "Password1!"),
+        ];
+        for (rule, word, expected) in cases {
+            assert_eq!(apply(rule, word).as_deref(), Some(expected), "rule {}", rule);
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_positions() {
+        assert_eq!(apply("D9", "abc").as_deref(), Some("abc"));
+        assert_eq!(apply("x25", "abc").as_deref(), Some("abc"));
+        assert_eq!(apply("i9!", "abc").as_deref(), Some("abc"));
+        assert_eq!(apply("T5", "abc").as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(matches!(parse_rule("X123"), Err(ParseError::Unsupported(1, 'X'))));
+        assert!(matches!(parse_rule("<5"), Err(ParseError::Unsupported(1, '<'))));
+        assert!(matches!(parse_rule("c w"), Err(ParseError::Unknown(3, 'w'))));
+        assert!(matches!(parse_rule("$"), Err(ParseError::MissingArgument(1, '$'))));
+        assert!(matches!(parse_rule("T?"), Err(ParseError::InvalidPosition(2, '?'))));
+    }
+}
@@ -0,0 +1,397 @@
+use super::Class;
+use super::Command;
+use super::END;
+use super::Matcher;
+use super::ParseError;
+use super::Rule;
+use super::position;
+
+use regex::Regex;
+use std::iter::Enumerate;
+use std::iter::Peekable;
+use std::str::Chars;
+
+type Tokens<'a> = Peekable<Enumerate<Chars<'a>>>;
+
+/// Parses all the rules contained in a rules file
+///
+/// If the file contains `[List.Rules:NAME]` sections, only the rules
+/// belonging to the given section are parsed, or those belonging to all
+/// the rules sections if no section is given. Otherwise, every line is
+/// treated as a rule.
+/// Empty lines and comments are skipped. Every rule is expanded by the
+/// preprocessor before being parsed. In case of failure, returns the line
+/// number, the line itself and the reason of the failure
+pub(super) fn parse_rules(content: &str, section: Option<&str>) -> Result<Vec<Rule>, (usize, String, ParseError)> {
+    let header = Regex::new(r"^\[[A-Za-z][\w.]*(:[^\]]*)?\]$").unwrap();
+    let rules_header = Regex::new(r"(?i)^\[List\.Rules:(.+)\]$").unwrap();
+    let has_sections = content.lines().any(|line| rules_header.is_match(line.trim()));
+    let mut in_section = !has_sections;
+    let mut rules = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        } else if has_sections && header.is_match(line.trim()) {
+            in_section = match rules_header.captures(line.trim()) {
+                Some(c) => section.is_none_or(|s| c[1].eq_ignore_ascii_case(s)),
+                None => false,
+            };
+            continue;
+        } else if !in_section {
+            continue;
+        }
+        let expanded = expand(line).map_err(|e| (n + 1, line.to_owned(), e))?;
+        for rule in expanded.iter() {
+            rules.push(parse_rule(rule).map_err(|e| (n + 1, rule.to_owned(), e))?);
+        }
+    }
+    Ok(rules)
+}
+
+/// Whether the content contains a rules section with the given name
+pub(super) fn has_section(content: &str, section: &str) -> bool {
+    let rules_header = Regex::new(r"(?i)^\[List\.Rules:(.+)\]$").unwrap();
+    content.lines()
+        .filter_map(|line| rules_header.captures(line.trim()))
+        .any(|c| c[1].eq_ignore_ascii_case(section))
+}
+
+/// Expands the preprocessor ranges of a rule into concrete rules
+///
+/// A range is enclosed in square brackets and can contain single characters
+/// as well as intervals (`a-z`). Backslashes escape the following character,
+/// while `\xHH` represents a character by its hexadecimal value.
+/// A rule containing multiple ranges expands to all their combinations.
+/// Parallel ranges (`\p[...]`), back-references (`\0` to `\9`) and
+/// repetitions (`\r[...]`) are not supported
+fn expand(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut rules = vec![String::new()];
+    let mut tokens = line.chars().enumerate().peekable();
+    while let Some((i, c)) = tokens.next() {
+        match c {
+            '\\' => match tokens.peek() {
+                Some((_, c @ ('p' | 'r' | '0'..='9'))) => {
+                    return Err(ParseError::UnsupportedPreprocessor(i + 1, *c));
+                },
+                _ => {
+                    let c = next_literal(&mut tokens, i + 1)?;
+                    rules.iter_mut().for_each(|r| r.push(c));
+                },
+            },
+            '[' => {
+                let range = next_range(&mut tokens, i + 1)?;
+                rules = rules.iter()
+                    .flat_map(|r| range.iter().map(move |c| format!("{}{}", r, c)))
+                    .collect();
+            },
+            _ => rules.iter_mut().for_each(|r| r.push(c)),
+        }
+    }
+    Ok(rules)
+}
+
+/// Consumes an escaped character and returns it
+fn next_literal(tokens: &mut Tokens, p: usize) -> Result<char, ParseError> {
+    match tokens.next() {
+        Some((_, 'x')) => {
+            let hex : String = (0..2).filter_map(|_| tokens.next().map(|(_, c)| c)).collect();
+            u8::from_str_radix(&hex, 16)
+                .map(char::from)
+                .map_err(|_| ParseError::InvalidEscape(p))
+        },
+        Some((_, c)) => Ok(c),
+        None => Err(ParseError::InvalidEscape(p)),
+    }
+}
+
+/// Consumes a preprocessor range and returns the characters it contains
+fn next_range(tokens: &mut Tokens, p: usize) -> Result<Vec<char>, ParseError> {
+    let mut range : Vec<char> = Vec::new();
+    loop {
+        let c = match tokens.next() {
+            None => return Err(ParseError::UnterminatedRange(p)),
+            Some((_, ']')) => break,
+            Some((i, '\\')) => next_literal(tokens, i + 1)?,
+            Some((_, c)) => c,
+        };
+        match tokens.peek() {
+            Some((_, '-')) => {
+                tokens.next();
+                let end = match tokens.next() {
+                    None => return Err(ParseError::UnterminatedRange(p)),
+                    Some((_, ']')) => {
+                        // trailing dash is a literal
+                        range.extend([c, '-']);
+                        break;
+                    },
+                    Some((i, '\\')) => next_literal(tokens, i + 1)?,
+                    Some((_, e)) => e,
+                };
+                if end < c {
+                    return Err(ParseError::InvalidInterval(p));
+                }
+                range.extend(c..=end);
+            },
+            _ => range.push(c),
+        }
+    }
+    Ok(range)
+}
+
+/// Parses a single rule in John the Ripper syntax
+///
+/// The rule shall have already been expanded by the preprocessor
+fn parse_rule(line: &str) -> Result<Rule, ParseError> {
+    let mut tokens = line.chars().enumerate().peekable();
+    let mut commands = Vec::new();
+    while let Some((i, f)) = tokens.next() {
+        let p = i + 1;
+        let command = match f {
+            ' ' | '\t' | ':' => Command::Noop,
+            'l' => Command::Lowercase,
+            'u' => Command::Uppercase,
+            'c' => Command::Capitalize,
+            'C' => Command::InvertCapitalize,
+            't' => Command::ToggleCase,
+            'T' => Command::ToggleAt(next_position(&mut tokens, p, f)?),
+            'r' => Command::Reverse,
+            'd' => Command::Duplicate,
+            'f' => Command::Reflect,
+            '{' => Command::RotateLeft,
+            '}' => Command::RotateRight,
+            '$' => Command::Append(next_char(&mut tokens, p, f)?),
+            '^' => Command::Prepend(next_char(&mut tokens, p, f)?),
+            '[' => Command::DeleteFirst,
+            ']' => Command::DeleteLast,
+            'D' => Command::DeleteAt(next_position(&mut tokens, p, f)?),
+            'x' => Command::Extract(next_position(&mut tokens, p, f)?, next_position(&mut tokens, p, f)?),
+            'O' => Command::Omit(next_position(&mut tokens, p, f)?, next_position(&mut tokens, p, f)?),
+            'i' => Command::Insert(next_position(&mut tokens, p, f)?, next_char(&mut tokens, p, f)?),
+            'o' => Command::Overwrite(next_position(&mut tokens, p, f)?, next_char(&mut tokens, p, f)?),
+            '\'' => Command::Truncate(next_position(&mut tokens, p, f)?),
+            's' => Command::Replace(next_matcher(&mut tokens, p, f)?, next_char(&mut tokens, p, f)?),
+            '@' => Command::Purge(next_matcher(&mut tokens, p, f)?),
+            'q' => Command::DuplicateAll,
+            'k' => Command::SwapFront,
+            'K' => Command::SwapBack,
+            '*' => Command::Swap(next_position(&mut tokens, p, f)?, next_position(&mut tokens, p, f)?),
+            'A' => Command::InsertString(next_position(&mut tokens, p, f)?, next_string(&mut tokens, p, f)?),
+            'S' => Command::ShiftCase,
+            'V' => Command::VowelsCase,
+            '<' => Command::RejectUnlessShorter(next_position(&mut tokens, p, f)?),
+            '>' => Command::RejectUnlessLonger(next_position(&mut tokens, p, f)?),
+            '_' => Command::RejectUnlessLength(next_position(&mut tokens, p, f)?),
+            '!' => Command::RejectIfContains(next_matcher(&mut tokens, p, f)?),
+            '/' => Command::RejectUnlessContains(next_matcher(&mut tokens, p, f)?),
+            '=' => Command::RejectUnlessAt(next_position(&mut tokens, p, f)?, next_matcher(&mut tokens, p, f)?),
+            '(' => Command::RejectUnlessFirst(next_matcher(&mut tokens, p, f)?),
+            ')' => Command::RejectUnlessLast(next_matcher(&mut tokens, p, f)?),
+            '%' => Command::RejectUnlessCount(next_position(&mut tokens, p, f)?, next_matcher(&mut tokens, p, f)?),
+            // flags depending on the hash type, always satisfied
+            '-' => {
+                match next_char(&mut tokens, p, f)? {
+                    'c' | '8' | 's' | 'p' | 'u' | 'U' | ':' => (),
+                    '<' | '>' => {
+                        next_position(&mut tokens, p, f)?;
+                    },
+                    c => return Err(ParseError::Unknown(p + 1, c)),
+                }
+                Command::Noop
+            },
+            // memory, grammar, keyboard and variables functions
+            'M' | 'Q' | 'X' | 'p' | 'P' | 'I' | 'L' | 'R' | 'v' => {
+                return Err(ParseError::Unsupported(p, f));
+            },
+            _ => {
+                return Err(ParseError::Unknown(p, f));
+            },
+        };
+        commands.push(command);
+    }
+    Ok(Rule { commands })
+}
+
+/// Consumes the next character and returns it as it is
+fn next_char(tokens: &mut Tokens, p: usize, f: char) -> Result<char, ParseError> {
+    tokens.next()
+        .map(|(_, c)| c)
+        .ok_or(ParseError::MissingArgument(p, f))
+}
+
+/// Consumes the next character and returns it as a position
+///
+/// In addition to the common positions, `z` represents the end of the entry.
+/// The other numeric constants are not supported
+fn next_position(tokens: &mut Tokens, p: usize, f: char) -> Result<usize, ParseError> {
+    match tokens.next().ok_or(ParseError::MissingArgument(p, f))? {
+        (_, 'z') => Ok(END),
+        (i, c @ ('*' | '-' | '+' | 'l' | 'm' | 'p')) => Err(ParseError::UnsupportedConstant(i + 1, c)),
+        (i, c) => position(c).ok_or(ParseError::InvalidPosition(i + 1, c)),
+    }
+}
+
+/// Consumes either a single character or a character class
+///
+/// Classes are introduced by `?`, with `??` representing the `?` itself.
+/// Uppercase class names match the characters not belonging to the class
+fn next_matcher(tokens: &mut Tokens, p: usize, f: char) -> Result<Matcher, ParseError> {
+    match next_char(tokens, p, f)? {
+        '?' => {
+            let (i, c) = tokens.next().ok_or(ParseError::MissingArgument(p, f))?;
+            let class = match c.to_ascii_lowercase() {
+                '?' => return Ok(Matcher::Char('?')),
+                'v' => Class::Vowels,
+                'c' => Class::Consonants,
+                'w' => Class::Whitespaces,
+                'p' => Class::Punctuation,
+                's' => Class::Symbols,
+                'l' => Class::Lowercase,
+                'u' => Class::Uppercase,
+                'd' => Class::Digits,
+                'a' => Class::Letters,
+                'x' => Class::Alphanumeric,
+                'o' => Class::Control,
+                'b' => Class::NonAscii,
+                'z' => Class::Any,
+                _ => return Err(ParseError::InvalidClass(i + 1, c)),
+            };
+            if c.is_ascii_uppercase() {
+                Ok(Matcher::NotClass(class))
+            } else {
+                Ok(Matcher::Class(class))
+            }
+        },
+        c => Ok(Matcher::Char(c)),
+    }
+}
+
+/// Consumes a string enclosed by an arbitrary delimiter
+fn next_string(tokens: &mut Tokens, p: usize, f: char) -> Result<String, ParseError> {
+    let delimiter = next_char(tokens, p, f)?;
+    let mut string = String::new();
+    loop {
+        match tokens.next() {
+            None => return Err(ParseError::UnterminatedString(p)),
+            Some((_, c)) if c == delimiter => break,
+            Some((_, c)) => string.push(c),
+        }
+    }
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::LengthUnit;
+
+    /// Applies a single rule to the word
+    fn apply(rule: &str, word: &str) -> Option<String> {
+        let Ok(parsed) = parse_rule(rule) else {
+            panic!("Failed to parse rule: {}", rule);
+        };
+        parsed.apply(word, LengthUnit::Chars)
+    }
+
+    /// Expands the rule, which is expected to be valid
+    fn expanded(rule: &str) -> Vec<String> {
+        let Ok(rules) = expand(rule) else {
+            panic!("Failed to expand rule: {}", rule);
+        };
+        rules
+    }
+
+    #[test]
+    fn reference_outputs() {
+        // examples from the documentation of John the Ripper
+        let cases = [
+            ("l", "Crack96", "crack96"),
+            ("u", "Crack96", "CRACK96"),
+            ("c", "crack96", "Crack96"),
+            ("C", "crack96", "cRACK96"),
+            ("t", "Crack96", "cRACK96"),
+            ("T0", "crack96", "Crack96"),
+            ("r", "Crack96", "69kcarC"),
+            ("d", "Crack96", "Crack96Crack96"),
+            ("f", "Crack96", "Crack9669kcarC"),
+            ("{", "jsmith", "smithj"),
+            ("}", "smithj", "jsmith"),
+            ("$1", "crack", "crack1"),
+            ("^1", "crack", "1crack"),
+            ("S", "Crack96", "cRACK(^"),
+            ("V", "Crack96", "CRaCK96"),
+            ("Az\"123\"", "crack", "crack123"),
+            ("A0\"12\"", "crack", "12crack"),
+            ("xz1", "crack", "crack"),
+            ("s?v*", "crack96", "cr*ck96"),
+            ("@?d", "crack96", "crack"),
+            ("/?d c", "crack96", "Crack96"),
+            ("-c -8 l", "Crack96", "crack96"),
+        ];
+        for (rule, word, expected) in cases {
+            assert_eq!(apply(rule, word).as_deref(), Some(expected), "rule {}", rule);
+        }
+    }
+
+    #[test]
+    fn rejections() {
+        assert_eq!(apply("<5", "abcd").as_deref(), Some("abcd"));
+        assert_eq!(apply("<5", "abcde"), None);
+        assert_eq!(apply(">3", "abc"), None);
+        assert_eq!(apply("_3", "abc").as_deref(), Some("abc"));
+        assert_eq!(apply("!?d", "abc1"), None);
+        assert_eq!(apply("/?d", "abc"), None);
+        assert_eq!(apply("=1b", "abc").as_deref(), Some("abc"));
+        assert_eq!(apply("(?u", "abc"), None);
+        assert_eq!(apply(")c", "abc").as_deref(), Some("abc"));
+        assert_eq!(apply("%2?v", "abc"), None);
+    }
+
+    #[test]
+    fn unsupported_functions() {
+        assert!(matches!(parse_rule("p"), Err(ParseError::Unsupported(1, 'p'))));
+        assert!(matches!(parse_rule("c M"), Err(ParseError::Unsupported(3, 'M'))));
+        assert!(matches!(parse_rule("s?Qa"), Err(ParseError::InvalidClass(3, 'Q'))));
+        assert!(matches!(parse_rule("'*"), Err(ParseError::UnsupportedConstant(2, '*'))));
+        assert!(matches!(parse_rule("c Dm"), Err(ParseError::UnsupportedConstant(4, 'm'))));
+        assert!(matches!(parse_rule("'!"), Err(ParseError::InvalidPosition(2, '!'))));
+    }
+
+    #[test]
+    fn preprocessor_expansion() {
+        assert_eq!(expanded("$[0-2]"), ["$0", "$1", "$2"]);
+        assert_eq!(expanded("[ab]$[xy]"), ["a$x", "a$y", "b$x", "b$y"]);
+        assert_eq!(expanded("$[a-c0]"), ["$a", "$b", "$c", "$0"]);
+        assert_eq!(expanded("$[a-]"), ["$a", "$-"]);
+        assert_eq!(expanded("$\\[ $[\\]\\x41]"), ["$[ $]", "$[ $A"]);
+        assert_eq!(expanded("c"), ["c"]);
+    }
+
+    #[test]
+    fn preprocessor_errors() {
+        assert!(matches!(expand("$[0-2"), Err(ParseError::UnterminatedRange(2))));
+        assert!(matches!(expand("$[c-a]"), Err(ParseError::InvalidInterval(2))));
+        assert!(matches!(expand("\\p[ab]$[xy]"), Err(ParseError::UnsupportedPreprocessor(1, 'p'))));
+        assert!(matches!(expand("$[ab]\\p1[xy]"), Err(ParseError::UnsupportedPreprocessor(6, 'p'))));
+        assert!(matches!(expand("$[0-9]$\\0"), Err(ParseError::UnsupportedPreprocessor(8, '0'))));
+        assert!(matches!(expand("\\r[0-9]"), Err(ParseError::UnsupportedPreprocessor(1, 'r'))));
+        assert!(matches!(expand("$\\xZZ"), Err(ParseError::InvalidEscape(2))));
+    }
+
+    #[test]
+    fn sections() {
+        let content = "[List.Rules:First]\nl\n[List.Rules:Second]\n$[12]\n[Options]\nfoo\n";
+        let Ok(all) = parse_rules(content, None) else {
+            panic!("Failed to parse rules");
+        };
+        assert_eq!(all.len(), 3);
+        let Ok(second) = parse_rules(content, Some("second")) else {
+            panic!("Failed to parse rules");
+        };
+        let candidates : Vec<String> = second.iter()
+            .filter_map(|rule| rule.apply("a", LengthUnit::Chars))
+            .collect();
+        assert_eq!(candidates, ["a1", "a2"]);
+        assert!(has_section(content, "first"));
+        assert!(!has_section(content, "third"));
+    }
+}
@@ -0,0 +1,675 @@
+mod hashcat;
+mod john;
+
+use super::file;
+use super::hex;
+use super::hex::byte_to_char;
+use super::hex::char_to_byte;
+use crate::params::LengthUnit;
+use crate::error::*;
+use crate::params::RulesSyntax;
+
+use std::io::Read;
+use std::path::PathBuf;
+
+/// A single rule, made of a sequence of commands
+/// that are applied one after the other
+pub struct Rule {
+    commands: Vec<Command>,
+}
+
+/// The position representing the end of the entry, whatever its length
+const END : usize = usize::MAX;
+
+/// The functions that a rule can be made of
+///
+/// Positions and lengths refer to characters, not bytes
+pub(crate) enum Command {
+    /// Do nothing
+    Noop,
+    /// Lowercase all the characters
+    Lowercase,
+    /// Uppercase all the characters
+    Uppercase,
+    /// Uppercase the first character and lowercase the rest
+    Capitalize,
+    /// Lowercase the first character and uppercase the rest
+    InvertCapitalize,
+    /// Toggle the case of all the characters
+    ToggleCase,
+    /// Toggle the case of the character at the given position
+    ToggleAt(usize),
+    /// Reverse the entire entry
+    Reverse,
+    /// Append the entry to itself
+    Duplicate,
+    /// Append the entry to itself the given number of times
+    DuplicateTimes(usize),
+    /// Append the reversed entry to itself
+    Reflect,
+    /// Move the first character to the end
+    RotateLeft,
+    /// Move the last character to the beginning
+    RotateRight,
+    /// Append a character
+    Append(char),
+    /// Prepend a character
+    Prepend(char),
+    /// Delete the first character
+    DeleteFirst,
+    /// Delete the last character
+    DeleteLast,
+    /// Delete the character at the given position
+    DeleteAt(usize),
+    /// Keep only the given number of characters starting from the given position
+    Extract(usize, usize),
+    /// Delete the given number of characters starting from the given position
+    Omit(usize, usize),
+    /// Insert a character at the given position
+    Insert(usize, char),
+    /// Overwrite the character at the given position
+    Overwrite(usize, char),
+    /// Truncate the entry at the given position
+    Truncate(usize),
+    /// Replace all the matching characters with another one
+    Replace(Matcher, char),
+    /// Remove all the matching characters
+    Purge(Matcher),
+    /// Duplicate the first character the given number of times
+    DuplicateFirst(usize),
+    /// Duplicate the last character the given number of times
+    DuplicateLast(usize),
+    /// Duplicate every character
+    DuplicateAll,
+    /// Swap the first two characters
+    SwapFront,
+    /// Swap the last two characters
+    SwapBack,
+    /// Swap the characters at the given positions
+    Swap(usize, usize),
+    /// Bitwise shift left the character at the given position
+    ShiftLeft(usize),
+    /// Bitwise shift right the character at the given position
+    ShiftRight(usize),
+    /// Increment by 1 the value of the character at the given position
+    Increment(usize),
+    /// Decrement by 1 the value of the character at the given position
+    Decrement(usize),
+    /// Replace the character at the given position with the following one
+    ReplaceNext(usize),
+    /// Replace the character at the given position with the preceding one
+    ReplacePrev(usize),
+    /// Duplicate the first given number of characters
+    DuplicateBlockFront(usize),
+    /// Duplicate the last given number of characters
+    DuplicateBlockBack(usize),
+    /// Lowercase everything and uppercase the first character
+    /// as well as every character following a space
+    Title,
+    /// Lowercase everything and uppercase the first character
+    /// as well as every character following the given separator
+    TitleSeparator(char),
+    /// Toggle the case of the character following
+    /// the Nth instance of the given character
+    ToggleAfter(usize, char),
+    /// Insert a string at the given position
+    InsertString(usize, String),
+    /// Replace every character with the one sharing its key
+    /// on a US keyboard, as if typed with Shift toggled
+    ShiftCase,
+    /// Lowercase the vowels and uppercase the consonants
+    VowelsCase,
+    /// Reject the entry unless it is shorter than the given length
+    RejectUnlessShorter(usize),
+    /// Reject the entry unless it is longer than the given length
+    RejectUnlessLonger(usize),
+    /// Reject the entry unless it is exactly as long as the given length
+    RejectUnlessLength(usize),
+    /// Reject the entry if it contains a matching character
+    RejectIfContains(Matcher),
+    /// Reject the entry unless it contains a matching character
+    RejectUnlessContains(Matcher),
+    /// Reject the entry unless the character at the given position matches
+    RejectUnlessAt(usize, Matcher),
+    /// Reject the entry unless the first character matches
+    RejectUnlessFirst(Matcher),
+    /// Reject the entry unless the last character matches
+    RejectUnlessLast(Matcher),
+    /// Reject the entry unless it contains at least
+    /// the given number of matching characters
+    RejectUnlessCount(usize, Matcher),
+}
+
+/// Matches either a single character or a class of characters
+#[derive(Clone, Copy)]
+pub(crate) enum Matcher {
+    Char(char),
+    Class(Class),
+    NotClass(Class),
+}
+
+/// The classes of characters that can be used in place of a single character
+#[derive(Clone, Copy)]
+pub(crate) enum Class {
+    Vowels,
+    Consonants,
+    Whitespaces,
+    Punctuation,
+    Symbols,
+    Lowercase,
+    Uppercase,
+    Digits,
+    Letters,
+    Alphanumeric,
+    Control,
+    NonAscii,
+    Any,
+}
+
+/// The reason why a rule could not be parsed
+pub(crate) enum ParseError {
+    /// The function exists but is not supported
+    Unsupported(usize, char),
+    /// The function does not exist
+    Unknown(usize, char),
+    /// The function requires more arguments than the ones provided
+    MissingArgument(usize, char),
+    /// The argument is not a valid position or length
+    InvalidPosition(usize, char),
+    /// The numeric constant exists but is not supported
+    UnsupportedConstant(usize, char),
+    /// The argument is not a valid class of characters
+    InvalidClass(usize, char),
+    /// The string argument is not terminated
+    UnterminatedString(usize),
+    /// The preprocessor range is not terminated
+    UnterminatedRange(usize),
+    /// The preprocessor escape sequence is not valid
+    InvalidEscape(usize),
+    /// The preprocessor interval ends before it starts
+    InvalidInterval(usize),
+    /// The preprocessor sequence exists but is not supported
+    UnsupportedPreprocessor(usize, char),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Unsupported(p, c) => write!(f, "Unsupported rule function '{}' at position {}", c, p),
+            ParseError::Unknown(p, c) => write!(f, "Unknown rule function '{}' at position {}", c, p),
+            ParseError::MissingArgument(p, c) => write!(f, "Missing argument for rule function '{}' at position {}", c, p),
+            ParseError::InvalidPosition(p, c) => write!(f, "Invalid position or length '{}' at position {}", c, p),
+            ParseError::UnsupportedConstant(p, c) => write!(f, "Unsupported numeric constant '{}' at position {}", c, p),
+            ParseError::InvalidClass(p, c) => write!(f, "Invalid character class '{}' at position {}", c, p),
+            ParseError::UnterminatedString(p) => write!(f, "Unterminated string starting at position {}", p),
+            ParseError::UnterminatedRange(p) => write!(f, "Unterminated range starting at position {}", p),
+            ParseError::InvalidEscape(p) => write!(f, "Invalid escape sequence at position {}", p),
+            ParseError::InvalidInterval(p) => write!(f, "Reversed interval in range starting at position {}", p),
+            ParseError::UnsupportedPreprocessor(p, c) => write!(f, "Unsupported preprocessor sequence '\\{}' at position {}", c, p),
+        }
+    }
+}
+
+/// Reads the rules file and parses all the rules it contains
+///
+/// When using the John the Ripper syntax, only the rules belonging to
+/// the given section are loaded, or those of all the sections if omitted.
+///
+/// Fails if the file cannot be read, if any of the rules cannot be parsed
+/// or if there are no rules at all
+pub fn load_rules(path: &PathBuf, syntax: RulesSyntax, section: Option<&str>) -> Result<Vec<Rule>, WormodError> {
+    let mut content = String::new();
+    if let Err(e) = file::open_input_file(path)?.read_to_string(&mut content) {
+        return Err(error!(
+            Io,
+            ("Failed to read rules file: {:?}", path),
+            ("Reason of the failure: {}", e.to_string())
+        ));
+    }
+    let rules = parse_rules(&content, syntax, section)?;
+    if rules.is_empty() {
+        return Err(error!(
+            Rules,
+            ("The rules file does not contain any rule: {:?}", path),
+            ("This is equivalent to a no-op")
+        ));
+    }
+    Ok(rules)
+}
+
+/// Parses all the rules contained in the given text
+///
+/// Sections are handled as in `load_rules`
+pub fn parse_rules(content: &str, syntax: RulesSyntax, section: Option<&str>) -> Result<Vec<Rule>, WormodError> {
+    if let (RulesSyntax::John, Some(section)) = (syntax, section) {
+        if !john::has_section(content, section) {
+            return Err(error!(
+                Rules,
+                ("Rules section not found: {}", section),
+                ("The rules file does not contain a [List.Rules:{}] section", section)
+            ));
+        }
+    }
+    let rules = match syntax {
+        RulesSyntax::Hashcat => hashcat::parse_rules(content),
+        RulesSyntax::John => john::parse_rules(content, section),
+    };
+    rules.map_err(|(n, line, e)| error!(
+        Rules,
+        ("Failed to parse rule at line {}: {}", n, line),
+        ("{}", e.to_string())
+    ))
+}
+
+/// Converts a position character into its numeric value
+///
+/// Positions go from 0 to 9 and then from A (10) to Z (35)
+fn position(c: char) -> Option<usize> {
+    match c {
+        '0'..='9' => Some(c as usize - '0' as usize),
+        'A'..='Z' => Some(c as usize - 'A' as usize + 10),
+        _ => None,
+    }
+}
+
+impl Rule {
+    /// Applies the rule to the given entry
+    ///
+    /// Lengths are measured in the given unit.
+    /// Returns `None` if the entry gets rejected by the rule
+    pub fn apply(&self, entry: &str, unit: LengthUnit) -> Option<String> {
+        let mut word : Vec<char> = entry.chars().collect();
+        self.apply_commands(&mut word, unit, false)
+            .then(|| word.into_iter().collect())
+    }
+
+    /// Applies the rule to the given raw entry
+    ///
+    /// ASCII bytes are treated as characters, while any other byte is
+    /// treated as an opaque symbol which has no case and belongs to no
+    /// class other than the non-ASCII one. Bytewise commands change any
+    /// byte into a single raw byte. Lengths are measured in bytes.
+    /// Returns `None` if the entry gets rejected by the rule
+    pub fn apply_bytes(&self, entry: &[u8]) -> Option<Vec<u8>> {
+        let mut word : Vec<char> = entry.iter().map(|b| byte_to_char(*b)).collect();
+        // every character stands for a single byte
+        if !self.apply_commands(&mut word, LengthUnit::Chars, true) {
+            return None;
+        }
+        let mut raw = Vec::with_capacity(word.len());
+        hex::restore(word.into_iter(), &mut raw);
+        Some(raw)
+    }
+
+    /// Applies all the commands in order
+    ///
+    /// Returns `false` if the word gets rejected
+    fn apply_commands(&self, word: &mut Vec<char>, unit: LengthUnit, raw: bool) -> bool {
+        self.commands.iter().all(|command| command.apply(word, unit, raw))
+    }
+}
+
+impl Command {
+    /// Applies the command to the given word
+    ///
+    /// Commands referring to positions that are out of bounds
+    /// leave the word untouched. Rejections based on the length of the
+    /// word measure it in the given unit. In raw mode, the characters
+    /// stand for the bytes of a raw entry, see [`byte_to_char`].
+    /// Returns `false` if the word gets rejected
+    fn apply(&self, w: &mut Vec<char>, unit: LengthUnit, raw: bool) -> bool {
+        let len = w.len();
+        match *self {
+            Command::Noop => (),
+            Command::Lowercase => w.iter_mut().for_each(|c| *c = lower(*c)),
+            Command::Uppercase => w.iter_mut().for_each(|c| *c = upper(*c)),
+            Command::Capitalize => {
+                w.iter_mut().for_each(|c| *c = lower(*c));
+                if let Some(c) = w.first_mut() {
+                    *c = upper(*c);
+                }
+            },
+            Command::InvertCapitalize => {
+                w.iter_mut().for_each(|c| *c = upper(*c));
+                if let Some(c) = w.first_mut() {
+                    *c = lower(*c);
+                }
+            },
+            Command::ToggleCase => w.iter_mut().for_each(|c| *c = toggle(*c)),
+            Command::ToggleAt(n) => {
+                if let Some(c) = w.get_mut(n) {
+                    *c = toggle(*c);
+                }
+            },
+            Command::Reverse => w.reverse(),
+            Command::Duplicate => w.extend_from_within(..),
+            Command::DuplicateTimes(n) => {
+                for _ in 0..n {
+                    w.extend_from_within(..len);
+                }
+            },
+            Command::Reflect => w.extend((0..len).rev().map(|i| w[i]).collect::<Vec<char>>()),
+            Command::RotateLeft => {
+                if len > 0 {
+                    w.rotate_left(1);
+                }
+            },
+            Command::RotateRight => {
+                if len > 0 {
+                    w.rotate_right(1);
+                }
+            },
+            Command::Append(c) => w.push(c),
+            Command::Prepend(c) => w.insert(0, c),
+            Command::DeleteFirst => {
+                if len > 0 {
+                    w.remove(0);
+                }
+            },
+            Command::DeleteLast => {
+                w.pop();
+            },
+            Command::DeleteAt(n) => {
+                if n < len {
+                    w.remove(n);
+                }
+            },
+            Command::Extract(n, m) => {
+                let m = if m == END { len.saturating_sub(n) } else { m };
+                if n < len && n + m <= len {
+                    w.truncate(n + m);
+                    w.drain(..n);
+                }
+            },
+            Command::Omit(n, m) => {
+                let m = if m == END { len.saturating_sub(n) } else { m };
+                if n < len && n + m <= len {
+                    w.drain(n..n + m);
+                }
+            },
+            Command::Insert(n, c) => {
+                let n = if n == END { len } else { n };
+                if n <= len {
+                    w.insert(n, c);
+                }
+            },
+            Command::Overwrite(n, c) => {
+                if let Some(x) = w.get_mut(n) {
+                    *x = c;
+                }
+            },
+            Command::Truncate(n) => w.truncate(n),
+            Command::Replace(x, y) => w.iter_mut().filter(|c| x.matches(**c)).for_each(|c| *c = y),
+            Command::Purge(x) => w.retain(|c| !x.matches(*c)),
+            Command::DuplicateFirst(n) => {
+                if let Some(&c) = w.first() {
+                    w.splice(0..0, std::iter::repeat_n(c, n));
+                }
+            },
+            Command::DuplicateLast(n) => {
+                if let Some(&c) = w.last() {
+                    w.extend(std::iter::repeat_n(c, n));
+                }
+            },
+            Command::DuplicateAll => {
+                *w = w.iter().flat_map(|&c| [c, c]).collect();
+            },
+            Command::SwapFront => {
+                if len > 1 {
+                    w.swap(0, 1);
+                }
+            },
+            Command::SwapBack => {
+                if len > 1 {
+                    w.swap(len - 2, len - 1);
+                }
+            },
+            Command::Swap(n, m) => {
+                if n < len && m < len {
+                    w.swap(n, m);
+                }
+            },
+            Command::ShiftLeft(n) => map_byte_at(w, n, raw, |b| b << 1),
+            Command::ShiftRight(n) => map_byte_at(w, n, raw, |b| b >> 1),
+            Command::Increment(n) => map_byte_at(w, n, raw, |b| b.wrapping_add(1)),
+            Command::Decrement(n) => map_byte_at(w, n, raw, |b| b.wrapping_sub(1)),
+            Command::ReplaceNext(n) => {
+                if n + 1 < len {
+                    w[n] = w[n + 1];
+                }
+            },
+            Command::ReplacePrev(n) => {
+                if n > 0 && n < len {
+                    w[n] = w[n - 1];
+                }
+            },
+            Command::DuplicateBlockFront(n) => {
+                if n <= len {
+                    w.splice(0..0, w[..n].to_vec());
+                }
+            },
+            Command::DuplicateBlockBack(n) => {
+                if n <= len {
+                    w.extend_from_within(len - n..);
+                }
+            },
+            Command::Title => title(w, ' '),
+            Command::TitleSeparator(s) => title(w, s),
+            Command::ToggleAfter(n, x) => {
+                let pos = w.iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == x)
+                    .nth(n)
+                    .map(|(i, _)| i + 1);
+                if let Some(c) = pos.and_then(|i| w.get_mut(i)) {
+                    *c = toggle(*c);
+                }
+            },
+            Command::InsertString(n, ref s) => {
+                let n = if n == END { len } else { n };
+                if n <= len {
+                    w.splice(n..n, s.chars());
+                }
+            },
+            Command::ShiftCase => w.iter_mut().for_each(|c| *c = shift(*c)),
+            Command::VowelsCase => w.iter_mut().for_each(|c| {
+                *c = if Class::Vowels.contains(*c) { lower(*c) } else { upper(*c) }
+            }),
+            Command::RejectUnlessShorter(n) => return measure(w, unit) < n,
+            Command::RejectUnlessLonger(n) => return measure(w, unit) > n,
+            Command::RejectUnlessLength(n) => return measure(w, unit) == n,
+            Command::RejectIfContains(x) => return !w.iter().any(|c| x.matches(*c)),
+            Command::RejectUnlessContains(x) => return w.iter().any(|c| x.matches(*c)),
+            Command::RejectUnlessAt(n, x) => return w.get(n).is_some_and(|c| x.matches(*c)),
+            Command::RejectUnlessFirst(x) => return w.first().is_some_and(|c| x.matches(*c)),
+            Command::RejectUnlessLast(x) => return w.last().is_some_and(|c| x.matches(*c)),
+            Command::RejectUnlessCount(n, x) => return w.iter().filter(|c| x.matches(**c)).count() >= n,
+        }
+        true
+    }
+}
+
+impl Matcher {
+    /// Whether the character matches
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            Matcher::Char(x) => c == x,
+            Matcher::Class(class) => class.contains(c),
+            Matcher::NotClass(class) => !class.contains(c),
+        }
+    }
+}
+
+impl Class {
+    /// Whether the character belongs to the class
+    fn contains(&self, c: char) -> bool {
+        match self {
+            Class::Vowels => "aeiouAEIOU".contains(c),
+            Class::Consonants => c.is_ascii_alphabetic() && !"aeiouAEIOU".contains(c),
+            Class::Whitespaces => c.is_whitespace(),
+            Class::Punctuation => ".,:;'\"?!`".contains(c),
+            Class::Symbols => "$%^&*()-_+=|\\<>[]{}#@/~".contains(c),
+            Class::Lowercase => c.is_lowercase(),
+            Class::Uppercase => c.is_uppercase(),
+            Class::Digits => c.is_ascii_digit(),
+            Class::Letters => c.is_alphabetic(),
+            Class::Alphanumeric => c.is_alphanumeric(),
+            Class::Control => c.is_control(),
+            Class::NonAscii => !c.is_ascii(),
+            Class::Any => true,
+        }
+    }
+}
+
+/// Lowercases the character, unless it doesn't map to a single character
+fn lower(c: char) -> char {
+    let mut l = c.to_lowercase();
+    match (l.next(), l.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+/// Uppercases the character, unless it doesn't map to a single character
+fn upper(c: char) -> char {
+    let mut u = c.to_uppercase();
+    match (u.next(), u.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+/// Toggles the case of the character
+fn toggle(c: char) -> char {
+    if c.is_lowercase() {
+        upper(c)
+    } else if c.is_uppercase() {
+        lower(c)
+    } else {
+        c
+    }
+}
+
+/// The characters typed by the keys of a US keyboard
+const UNSHIFTED : &str = "`1234567890-=\\qwertyuiop[]asdfghjkl;'zxcvbnm,./";
+
+/// The characters typed by the same keys while holding Shift
+const SHIFTED : &str = "~!@#$%^&*()_+|QWERTYUIOP{}ASDFGHJKL:\"ZXCVBNM<>?";
+
+/// Replaces the character with the one sharing its key on a US keyboard,
+/// unless it is not on the keyboard
+fn shift(c: char) -> char {
+    // both are made of ASCII characters only
+    if let Some(i) = UNSHIFTED.find(c) {
+        char::from(SHIFTED.as_bytes()[i])
+    } else if let Some(i) = SHIFTED.find(c) {
+        char::from(UNSHIFTED.as_bytes()[i])
+    } else {
+        c
+    }
+}
+
+/// Lowercases the word and uppercases the first character
+/// as well as every character following the separator
+fn title(w: &mut [char], separator: char) {
+    let mut capitalize = true;
+    for c in w.iter_mut() {
+        *c = if capitalize { upper(*c) } else { lower(*c) };
+        capitalize = *c == separator;
+    }
+}
+
+/// Measures the length of the word in the given unit
+///
+/// Words containing escaped raw bytes are measured in bytes,
+/// see [`LengthUnit::measure`]
+fn measure(w: &[char], unit: LengthUnit) -> usize {
+    match unit {
+        LengthUnit::Chars if !w.iter().any(|c| hex::is_raw_byte(*c)) => w.len(),
+        _ => unit.measure(&w.iter().collect::<String>()),
+    }
+}
+
+/// Applies a bytewise operation to the character at the given position
+///
+/// Only characters that fit in a single byte are affected. In raw mode,
+/// those are the characters standing for raw bytes, and so is the result
+fn map_byte_at(w: &mut [char], n: usize, raw: bool, f: impl Fn(u8) -> u8) {
+    let Some(c) = w.get_mut(n) else {
+        return;
+    };
+    if raw {
+        if let Some(b) = char_to_byte(*c) {
+            *c = byte_to_char(f(b));
+        }
+    } else if let Ok(b) = u8::try_from(*c) {
+        *c = char::from(f(b));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the rules, which are expected to be valid
+    fn rules(content: &str, syntax: RulesSyntax) -> Vec<Rule> {
+        let parsed = match syntax {
+            RulesSyntax::Hashcat => hashcat::parse_rules(content),
+            RulesSyntax::John => john::parse_rules(content, None),
+        };
+        let Ok(rules) = parsed else {
+            panic!("Failed to parse rules: {}", content);
+        };
+        rules
+    }
+
+    #[test]
+    fn bytewise_commands_on_raw_entries() {
+        let rules = rules("L0\nR0\n+0\n-0\n", RulesSyntax::Hashcat);
+        let apply = |entry: &[u8]| -> Vec<Vec<u8>> {
+            rules.iter().filter_map(|rule| rule.apply_bytes(entry)).collect()
+        };
+        // results stay single bytes, even when not ASCII
+        assert_eq!(apply(b"a"), [vec![0xC2], vec![0x30], vec![0x62], vec![0x60]]);
+        assert_eq!(apply(&[0xC3, b'x']), [vec![0x86, b'x'], vec![0x61, b'x'], vec![0xC4, b'x'], vec![0xC2, b'x']]);
+        assert_eq!(apply(&[0xFF]), [vec![0xFE], vec![0x7F], vec![0x00], vec![0xFE]]);
+    }
+
+    #[test]
+    fn case_commands_on_raw_entries() {
+        let rules = rules("u\nl\nt\nS\n", RulesSyntax::John);
+        let candidates : Vec<Vec<u8>> = rules.iter()
+            .filter_map(|rule| rule.apply_bytes(&[b'a', 0xE9, b'B', b'1']))
+            .collect();
+        // non-ASCII bytes have no case
+        assert_eq!(candidates, [
+            vec![b'A', 0xE9, b'B', b'1'],
+            vec![b'a', 0xE9, b'b', b'1'],
+            vec![b'A', 0xE9, b'b', b'1'],
+            vec![b'A', 0xE9, b'b', b'!'],
+        ]);
+    }
+
+    #[test]
+    fn arguments_of_raw_entries() {
+        let rules = rules("$é\nr\n", RulesSyntax::Hashcat);
+        let candidates : Vec<Vec<u8>> = rules.iter()
+            .filter_map(|rule| rule.apply_bytes(&[b'a', 0xE9]))
+            .collect();
+        assert_eq!(candidates, [vec![b'a', 0xE9, 0xC3, 0xA9], vec![0xE9, b'a']]);
+    }
+
+    #[test]
+    fn lengths_in_units() {
+        let rules = rules("<3\n", RulesSyntax::John);
+        let combined = "e\u{301}e\u{301}";
+        assert_eq!(rules[0].apply(combined, LengthUnit::Graphemes).as_deref(), Some(combined));
+        assert_eq!(rules[0].apply(combined, LengthUnit::Chars), None);
+        // raw entries are measured in bytes
+        assert_eq!(rules[0].apply_bytes(&[0xE9, 0xE9, 0xE9]), None);
+        // and so are entries containing escaped raw bytes
+        let escaped = "a\u{F7FF}";
+        assert_eq!(rules[0].apply(escaped, LengthUnit::Bytes).as_deref(), Some(escaped));
+        assert_eq!(rules[0].apply("a\u{F7C3}\u{F7A9}", LengthUnit::Graphemes), None);
+    }
+}
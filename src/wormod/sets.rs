@@ -0,0 +1,285 @@
+use super::decoder;
+use super::decoder::LineDecoder;
+use super::entry::Entry;
+use super::memory;
+use super::reader;
+use super::reader::Reader;
+use super::wordlist::Wordlist;
+use crate::error::*;
+use crate::params::Params;
+use crate::params::SetOperation;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Tracks in how many inputs an entry is contained
+#[derive(Clone, Copy)]
+struct Membership {
+    /// The number of inputs containing the entry
+    count: usize,
+    /// Whether the entry is contained in the first input
+    in_first: bool,
+    /// The index of the last input the entry was found in
+    last: usize,
+}
+
+impl SetOperation {
+    /// Whether an entry shall be kept according to its membership
+    fn keeps(&self, m: Membership, n_inputs: usize) -> bool {
+        match self {
+            SetOperation::Union => true,
+            SetOperation::Intersection => m.count == n_inputs,
+            SetOperation::Difference => m.in_first && m.count == 1,
+            SetOperation::SymmetricDifference => m.count == 1,
+        }
+    }
+}
+
+/// Combines all the input wordlists by storing them in memory
+///
+/// The resulting entries are unique and retain the order
+/// in which they first appear in the inputs
+pub(super) fn combine<E: Entry>(params: &Params, operation: SetOperation) -> Result<Wordlist<E>, WormodError> {
+    let readers : Vec<(Reader, usize)> = params.input.iter()
+        .map(|in_path| reader::buffered_file_reader(params, in_path))
+        .collect::<Result<_, _>>()?;
+    {
+        let total_size : usize = readers.iter().map(|(_, size)| size).sum();
+        let available_memory = memory::available_memory();
+        if !memory::is_memory_enough_with(available_memory, total_size.saturating_mul(2)) {
+            return Err(error!(
+                Memory,
+                ("Available memory is too low"),
+                ("Not enough memory to store all the input wordlists, see --sorted-inputs")
+            ));
+        }
+    }
+    let mut decoder = LineDecoder::new(params.invalid_utf8, params.decode_hex);
+    let wordlists : Vec<Wordlist<E>> = params.input.iter().zip(readers)
+        .map(|(in_path, (buf_reader, file_size))| match reader::can_map(params, file_size) {
+            true => reader::map_all(params, in_path, &mut decoder),
+            false => reader::read_all(buf_reader, file_size, &mut decoder),
+        })
+        .collect::<Result<_, _>>()?;
+    decoder::report(params.invalid_utf8, decoder.n_invalid());
+    {
+        let n_entries : usize = wordlists.iter().map(|w| w.len()).sum();
+        let entry_size = std::mem::size_of::<&[u8]>() + std::mem::size_of::<Membership>();
+        let available_memory = memory::available_memory();
+        if !memory::is_memory_enough_with(available_memory, n_entries.saturating_mul(entry_size * 2)) {
+            return Err(error!(
+                Memory,
+                ("Not enough memory to complete the operation(s)")
+            ));
+        }
+    }
+
+    let mut memberships : HashMap<&[u8], Membership> = HashMap::new();
+    for (i, wordlist) in wordlists.iter().enumerate() {
+        for entry in wordlist.iter() {
+            let m = memberships.entry(entry).or_insert(Membership {
+                count: 0,
+                in_first: i == 0,
+                last: usize::MAX,
+            });
+            if m.last != i {
+                m.count += 1;
+                m.last = i;
+            }
+        }
+    }
+
+    let n_inputs = wordlists.len();
+    let mut emitted = HashSet::new();
+    let mut combined = Wordlist::new();
+    wordlists.iter()
+        .flat_map(|w| w.iter())
+        .filter(|e| operation.keeps(memberships[e], n_inputs))
+        .filter(|e| emitted.insert(*e))
+        .for_each(|e| combined.push(e));
+    Ok(combined)
+}
+
+/// Combines sorted input wordlists while reading them
+pub(super) struct SortedMerge<E: Entry> {
+    operation: SetOperation,
+    paths: Vec<PathBuf>,
+    readers: Vec<Reader>,
+    decoders: Vec<LineDecoder>,
+    /// The current entry of each input, `None` once the EOF is reached
+    heads: Vec<Option<E>>,
+    line: E,
+}
+
+impl<E: Entry> SortedMerge<E> {
+    pub(super) fn new(params: &Params, operation: SetOperation) -> Result<Self, WormodError> {
+        let readers = params.input.iter()
+            .map(|in_path| reader::buffered_file_reader(params, in_path).map(|(r, _)| r))
+            .collect::<Result<Vec<Reader>, _>>()?;
+        let mut merge = Self {
+            operation,
+            paths: params.input.clone(),
+            heads: vec![None; readers.len()],
+            decoders: readers.iter().map(|_| LineDecoder::new(params.invalid_utf8, params.decode_hex)).collect(),
+            readers,
+            line: E::default(),
+        };
+        for i in 0..merge.readers.len() {
+            merge.advance(i)?;
+        }
+        Ok(merge)
+    }
+
+    /// Reads the next combined entry into the buffer
+    ///
+    /// Returns `false` when all the inputs have been consumed
+    pub(super) fn next_entry(&mut self, buffer: &mut E) -> Result<bool, WormodError> {
+        let n_inputs = self.readers.len();
+        loop {
+            let Some(min) = self.heads.iter().flatten().min().cloned() else {
+                return Ok(false);
+            };
+            let mut m = Membership { count: 0, in_first: false, last: 0 };
+            for i in 0..n_inputs {
+                if self.heads[i].as_ref() == Some(&min) {
+                    m.count += 1;
+                    m.in_first |= i == 0;
+                    self.advance(i)?;
+                }
+            }
+            if self.operation.keeps(m, n_inputs) {
+                *buffer = min;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// The number of lines containing invalid UTF-8 sequences
+    pub(super) fn n_invalid(&self) -> usize {
+        self.decoders.iter().map(|d| d.n_invalid()).sum()
+    }
+
+    /// Moves the given input to its next distinct entry
+    ///
+    /// Fails if the input is not sorted
+    fn advance(&mut self, i: usize) -> Result<(), WormodError> {
+        loop {
+            self.line.clear();
+            if !reader::pipe_read(&mut self.readers[i], &mut self.decoders[i], &mut self.line)? {
+                self.heads[i] = None;
+                return Ok(());
+            }
+            self.line.trim_whitespaces();
+            let entry = &self.line;
+            if entry.is_empty() {
+                continue;
+            }
+            match self.heads[i].as_ref() {
+                Some(prev) if entry == prev => continue,
+                Some(prev) if entry < prev => {
+                    return Err(error!(
+                        InvalidOptions,
+                        ("Input wordlist is not sorted: {:?}", self.paths[i]),
+                        ("Entry {:?} comes after {:?}", String::from_utf8_lossy(entry.as_bytes()), String::from_utf8_lossy(prev.as_bytes()))
+                    ));
+                },
+                _ => {
+                    self.heads[i] = Some(std::mem::take(&mut self.line));
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::TempDir;
+    use clap::Parser;
+
+    const OPERATIONS: [SetOperation; 4] = [
+        SetOperation::Union,
+        SetOperation::Intersection,
+        SetOperation::Difference,
+        SetOperation::SymmetricDifference,
+    ];
+
+    /// Combines the given wordlists written to temporary files,
+    /// in memory and while reading them if they are sorted
+    fn combined(inputs: &[&str], operation: SetOperation, sorted: bool) -> Vec<String> {
+        let tmp_dir = TempDir::new();
+        let mut params = Params::parse_from(["wormod"]);
+        params.input = inputs.iter().enumerate()
+            .map(|(i, content)| tmp_dir.file(&format!("input{}", i), content))
+            .collect();
+        if !sorted {
+            return combine::<String>(&params, operation).unwrap().entries().collect();
+        }
+        let mut merge = SortedMerge::new(&params, operation).unwrap();
+        let mut entries = Vec::new();
+        let mut entry = String::new();
+        while merge.next_entry(&mut entry).unwrap() {
+            entries.push(std::mem::take(&mut entry));
+        }
+        entries
+    }
+
+    #[test]
+    fn memberships() {
+        let only_first = Membership { count: 1, in_first: true, last: 0 };
+        let only_other = Membership { count: 1, in_first: false, last: 1 };
+        let in_all = Membership { count: 3, in_first: true, last: 2 };
+        let results : Vec<[bool; 3]> = OPERATIONS.iter()
+            .map(|op| [op.keeps(only_first, 3), op.keeps(only_other, 3), op.keeps(in_all, 3)])
+            .collect();
+        assert_eq!(results, [
+            [true, true, true],
+            [false, false, true],
+            [true, false, false],
+            [true, true, false],
+        ]);
+    }
+
+    #[test]
+    fn combines_in_memory() {
+        let inputs = ["x\ny\nz\ny\n", "y\nw\n\nz\n"];
+        let expected : [&[&str]; 4] = [
+            &["x", "y", "z", "w"],
+            &["y", "z"],
+            &["x"],
+            &["x", "w"],
+        ];
+        for (op, expected) in OPERATIONS.into_iter().zip(expected) {
+            assert_eq!(combined(&inputs, op, false), expected);
+        }
+    }
+
+    #[test]
+    fn combines_sorted_inputs() {
+        let inputs = ["a\nb\nb\nd\n", "b\nc\nd\n", "b\nd\ne\n"];
+        let expected : [&[&str]; 4] = [
+            &["a", "b", "c", "d", "e"],
+            &["b", "d"],
+            &["a"],
+            &["a", "c", "e"],
+        ];
+        for (op, expected) in OPERATIONS.into_iter().zip(expected) {
+            assert_eq!(combined(&inputs, op, true), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_unsorted_inputs() {
+        let tmp_dir = TempDir::new();
+        let mut params = Params::parse_from(["wormod"]);
+        params.input = vec![tmp_dir.file("input", "b\na\n")];
+        let result = SortedMerge::<String>::new(&params, SetOperation::Union).and_then(|mut merge| {
+            let mut entry = String::new();
+            while merge.next_entry(&mut entry)? {}
+            Ok(())
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use super::decoder;
+use super::decoder::LineDecoder;
+use super::dedup;
+use super::entry::Entry;
+use super::memory;
+use super::reader;
+use crate::cli::StatsParams;
+use crate::error::*;
+use crate::params::HashAlgorithm;
+use crate::params::LengthUnit;
+
+use std::collections::BTreeMap;
+
+/// The classes of characters entries are made of
+const CLASSES : [&str; 7] = [
+    "Lowercase letters",
+    "Uppercase letters",
+    "Digits",
+    "Mixed-case letters",
+    "Letters and digits",
+    "Printable ASCII",
+    "Other",
+];
+
+/// Statistics about the entries of a wordlist
+#[derive(Default)]
+struct Stats {
+    /// The number of lines read, including empty ones
+    n_lines: usize,
+    /// The number of entries
+    n_entries: usize,
+    /// The number of distinct entries
+    n_unique: usize,
+    /// The sum of the lengths of all the entries
+    total_length: usize,
+    /// How many entries have each length
+    lengths: BTreeMap<usize, usize>,
+    /// How many entries belong to each class, see `CLASSES`
+    classes: [usize; CLASSES.len()],
+}
+
+impl Stats {
+    /// Accounts for the entry
+    fn record<E: Entry>(&mut self, entry: &E, unit: LengthUnit, unique: bool) {
+        let length = entry.length(unit);
+        self.n_entries += 1;
+        self.n_unique += unique as usize;
+        self.total_length += length;
+        *self.lengths.entry(length).or_default() += 1;
+        self.classes[class_of(entry.as_bytes())] += 1;
+    }
+
+    /// Writes the statistics to the standard output
+    fn print(&self, unit: LengthUnit, with_unique: bool) {
+        let percent = |n: usize| n as f64 * 100.0 / self.n_entries.max(1) as f64;
+        println!("Lines: {}", self.n_lines);
+        println!("Entries: {}", self.n_entries);
+        if with_unique {
+            println!("Unique entries: {} ({:.2}%)", self.n_unique, percent(self.n_unique));
+        }
+        if self.n_entries == 0 {
+            return;
+        }
+        let shortest = self.lengths.keys().next().unwrap_or(&0);
+        let longest = self.lengths.keys().next_back().unwrap_or(&0);
+        println!("Shortest entry: {} {}", shortest, unit.name());
+        println!("Longest entry: {} {}", longest, unit.name());
+        println!("Average length: {:.2} {}", self.total_length as f64 / self.n_entries as f64, unit.name());
+        println!("Length distribution:");
+        for (length, n) in self.lengths.iter() {
+            println!("  {:>6}  {:>12}  {:>6.2}%", length, n, percent(*n));
+        }
+        println!("Character classes:");
+        for (class, n) in CLASSES.iter().zip(self.classes.iter()) {
+            println!("  {:<20}  {:>12}  {:>6.2}%", class, n, percent(*n));
+        }
+    }
+}
+
+/// The index in `CLASSES` of the class the entry belongs to
+fn class_of(entry: &[u8]) -> usize {
+    let all = |f: fn(&u8) -> bool| entry.iter().all(f);
+    if all(u8::is_ascii_lowercase) {
+        0
+    } else if all(u8::is_ascii_uppercase) {
+        1
+    } else if all(u8::is_ascii_digit) {
+        2
+    } else if all(u8::is_ascii_alphabetic) {
+        3
+    } else if all(u8::is_ascii_alphanumeric) {
+        4
+    } else if all(|b| b.is_ascii_graphic() || *b == b' ') {
+        5
+    } else {
+        6
+    }
+}
+
+/// Reads the whole input and writes statistics about its entries
+pub(super) fn stats<E: Entry>(params: &StatsParams) -> Result<(), WormodError> {
+    let io_params = params.io_params();
+    memory::set_memory_limit(io_params.memory_limit);
+    let (mut buf_reader, _) = reader::buffered_reader(&io_params)?;
+    let mut decoder = LineDecoder::new(io_params.invalid_utf8, io_params.decode_hex);
+    let mut seen = (!params.skip_unique).then(|| dedup::unique_set(HashAlgorithm::Siphash));
+
+    let mut stats = Stats::default();
+    let mut buffer = E::default();
+    loop {
+        buffer.clear();
+        if !reader::pipe_read(&mut buf_reader, &mut decoder, &mut buffer)? {
+            // reached EOF
+            break;
+        }
+        stats.n_lines += 1;
+
+        buffer.trim_whitespaces();
+        if buffer.is_empty() {
+            continue;
+        }
+        let unique = match seen.as_mut() {
+            Some(seen) => seen.insert(buffer.as_bytes())?,
+            None => false,
+        };
+        stats.record(&buffer, params.length_unit, unique);
+    }
+
+    decoder::report(io_params.invalid_utf8, decoder.n_invalid());
+    stats.print(params.length_unit, seen.is_some());
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! Helpers shared by the unit tests
+
+use super::file;
+
+use std::io::Cursor;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A temporary directory, removed along with its content once dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> Self {
+        Self(file::create_temp_dir(&std::env::temp_dir()).unwrap())
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes a file with the given content in the directory
+    /// and returns its path
+    pub(crate) fn file(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        file::remove_temp_dir(&self.0);
+    }
+}
+
+/// An input stream returning a single byte per read, like a slow pipe
+pub(crate) struct ByteByByte(pub(crate) Cursor<Vec<u8>>);
+
+impl Read for ByteByByte {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
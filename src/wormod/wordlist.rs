@@ -1,56 +1,503 @@
+use super::dedup::Fnv1a;
+use super::entry::Entry;
 use super::memory;
-use crate::print::*;
+use super::rules::Rule;
+use crate::params::HashAlgorithm;
+use crate::error::*;
+use crate::params::LengthUnit;
 
-pub(super) trait FromBuffer {
-    fn from_buffer(buffer: String) -> Self;
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::BuildHasherDefault;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::ops::Range;
+
+use memmap2::MmapMut;
+use memmap2::MmapOptions;
+use rayon::prelude::*;
+
+/// The raw content of a whole input
+///
+/// The content is either read into memory or, for files, mapped in memory
+/// as a private copy, so that changing it does not change the file
+pub struct RawBuffer {
+    storage: Storage,
+}
+
+enum Storage {
+    Owned(Vec<u8>),
+    Mapped(MmapMut),
+}
+
+impl RawBuffer {
+    /// Maps the whole file in memory
+    pub(super) fn map_file(file: &File) -> std::io::Result<Self> {
+        // SAFETY: the mapping is private, and the file is only read by
+        // WorMod, which is fine as long as it is not truncated meanwhile
+        let map = unsafe { MmapOptions::new().map_copy(file)? };
+        Ok(Self { storage: Storage::Mapped(map) })
+    }
+
+    /// Returns the content as a vector, copying it if mapped
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.storage {
+            Storage::Owned(buffer) => buffer,
+            Storage::Mapped(map) => map.to_vec(),
+        }
+    }
+
+    /// Returns the content as a vector which can grow,
+    /// copying it first if mapped
+    fn as_vec(&mut self) -> &mut Vec<u8> {
+        if let Storage::Mapped(map) = &self.storage {
+            self.storage = Storage::Owned(map.to_vec());
+        }
+        match &mut self.storage {
+            Storage::Owned(buffer) => buffer,
+            Storage::Mapped(_) => unreachable!(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for RawBuffer {
+    fn from(buffer: Vec<u8>) -> Self {
+        Self { storage: Storage::Owned(buffer) }
+    }
+}
+
+impl Deref for RawBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match &self.storage {
+            Storage::Owned(buffer) => buffer,
+            Storage::Mapped(map) => map,
+        }
+    }
+}
+
+impl DerefMut for RawBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.storage {
+            Storage::Owned(buffer) => buffer,
+            Storage::Mapped(map) => map,
+        }
+    }
+}
+
+/// A whole wordlist stored in a single buffer
+///
+/// The content of the entries is not stored on its own, rather each entry
+/// is the position and length of its content in the buffer, so that sorting,
+/// removing duplicates and filtering only move the positions around. The
+/// content in the buffer is known to be valid for the type of entries
+pub struct Wordlist<E: Entry> {
+    buffer: RawBuffer,
+    index: Vec<Span>,
+    entry: PhantomData<E>,
+}
+
+/// The position and length of the content of an entry
+#[derive(Clone, Copy)]
+struct Span {
+    start: usize,
+    len: usize,
+}
+
+impl Span {
+    fn range(&self) -> Range<usize> {
+        self.start..self.start + self.len
+    }
+}
+
+impl<E: Entry> Default for Wordlist<E> {
+    fn default() -> Self {
+        Self {
+            buffer: RawBuffer::from(Vec::new()),
+            index: Vec::new(),
+            entry: PhantomData,
+        }
+    }
+}
+
+impl<E: Entry> Wordlist<E> {
+    /// Builds an empty wordlist
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Splits the content of the buffer between `start` and `end` into
+    /// entries, without copying it
+    ///
+    /// Empty lines are skipped
+    pub(super) fn split(buffer: RawBuffer, start: usize, end: usize) -> Result<Self, WormodError> {
+        let bounds = line_bounds(&buffer, start, end, rayon::current_num_threads());
+        let counts : Vec<usize> = bounds.par_windows(2)
+            .map(|b| buffer[b[0]..b[1]].split(|b| *b == b'\n').filter(|e| !e.is_empty()).count())
+            .collect();
+        let n_entries = counts.iter().sum();
+        check_index_memory(n_entries)?;
+        let mut index = vec![Span { start: 0, len: 0 }; n_entries];
+        // each part of the buffer fills its own part of the index
+        let mut index_parts = Vec::with_capacity(counts.len());
+        let mut rest = index.as_mut_slice();
+        for count in counts.iter() {
+            let (part, tail) = std::mem::take(&mut rest).split_at_mut(*count);
+            index_parts.push(part);
+            rest = tail;
+        }
+        index_parts.into_par_iter().zip(bounds.par_windows(2)).for_each(|(part, b)| {
+            let mut spans = part.iter_mut();
+            let mut line_start = b[0];
+            for line in buffer[b[0]..b[1]].split(|b| *b == b'\n') {
+                if !line.is_empty() {
+                    *spans.next().unwrap() = Span { start: line_start, len: line.len() };
+                }
+                line_start += line.len() + 1;
+            }
+        });
+        Ok(Self {
+            buffer,
+            index,
+            entry: PhantomData,
+        })
+    }
+
+    /// The number of entries
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Whether there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The total size of the content of the entries
+    pub fn content_size(&self) -> usize {
+        self.index.iter().map(|s| s.len).sum()
+    }
+
+    /// Appends an entry, copying its content at the end of the buffer
+    ///
+    /// The content must be valid for the type of entries
+    pub fn push(&mut self, content: &[u8]) {
+        let buffer = self.buffer.as_vec();
+        self.index.push(Span { start: buffer.len(), len: content.len() });
+        buffer.extend_from_slice(content);
+    }
+
+    /// The content of the entries, in order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item=&[u8]> + ExactSizeIterator + '_ {
+        self.index.iter().map(|s| &self.buffer[s.range()])
+    }
+
+    /// The entries, in order, each one built by copying its content
+    pub fn entries(&self) -> impl Iterator<Item=E> + '_ {
+        self.iter().map(|e| E::from_valid_bytes(e.to_vec()))
+    }
+
+    /// Retains only the entries whose content satisfies the predicate
+    pub fn retain(&mut self, f: impl Fn(&[u8]) -> bool + Sync) {
+        let buffer = &self.buffer;
+        let keep : Vec<bool> = self.index.par_iter().map(|s| f(&buffer[s.range()])).collect();
+        self.retain_marked(&keep);
+    }
+
+    /// Sorts the entries
+    pub fn sort(&mut self) {
+        let buffer = &self.buffer;
+        self.index.par_sort_by(|a, b| buffer[a.range()].cmp(&buffer[b.range()]));
+    }
+
+    /// Removes consecutive repeated entries
+    pub fn dedup(&mut self) {
+        let buffer = &self.buffer;
+        self.index.dedup_by(|a, b| buffer[a.range()] == buffer[b.range()]);
+    }
+
+    /// Reverses the content of each entry in place
+    ///
+    /// As long as the entries are in the same order as their content,
+    /// the buffer is split among the threads
+    pub fn reverse_entries(&mut self) {
+        let in_order = self.index.par_windows(2).all(|w| w[0].start < w[1].start);
+        if !in_order {
+            for s in self.index.iter() {
+                E::reverse_content(&mut self.buffer[s.range()]);
+            }
+            return;
+        }
+        let part_len = self.index.len().div_ceil(rayon::current_num_threads()).max(1);
+        let mut parts = Vec::new();
+        let mut rest = &mut self.buffer[..];
+        let mut offset = 0;
+        for spans in self.index.chunks(part_len) {
+            let part_end = spans.last().unwrap().range().end;
+            let (part, tail) = std::mem::take(&mut rest).split_at_mut(part_end - offset);
+            parts.push((part, offset, spans));
+            rest = tail;
+            offset = part_end;
+        }
+        parts.into_par_iter().for_each(|(part, offset, spans)| {
+            for s in spans {
+                E::reverse_content(&mut part[s.start - offset..s.start - offset + s.len]);
+            }
+        });
+    }
+
+    /// Replaces the content of each entry for which `f` returns
+    /// a replacement, which must not be longer than the content
+    pub(super) fn shrink_entries(&mut self, mut f: impl FnMut(&[u8]) -> Option<Vec<u8>>) {
+        for s in self.index.iter_mut() {
+            if let Some(replacement) = f(&self.buffer[s.range()]) {
+                assert!(replacement.len() <= s.len);
+                s.len = replacement.len();
+                self.buffer[s.range()].copy_from_slice(&replacement);
+            }
+        }
+    }
+
+    /// Retains only the entries marked to be kept
+    pub(super) fn retain_marked(&mut self, keep: &[bool]) {
+        let mut marks = keep.iter();
+        self.index.retain(|_| *marks.next().unwrap());
+    }
+}
+
+/// Splits the content of the buffer between `start` and `end` into
+/// about `n` parts of similar size, each one made of whole lines
+///
+/// Returns the bounds of the parts, including `start` and `end`
+fn line_bounds(buffer: &[u8], start: usize, end: usize, n: usize) -> Vec<usize> {
+    let part_size = (end - start) / n + 1;
+    let mut bounds = vec![start];
+    let mut last = start;
+    while end - last > part_size {
+        match buffer[last + part_size..end].iter().position(|b| *b == b'\n') {
+            Some(p) => {
+                last += part_size + p + 1;
+                bounds.push(last);
+            },
+            None => break,
+        }
+    }
+    bounds.push(end);
+    bounds
+}
+
+/// Splits the content of a whole input into entries
+pub trait FromBuffer: Sized {
+    type Buffer;
+    fn from_buffer(buffer: Self::Buffer) -> Result<Self, WormodError>;
+}
+
+impl FromBuffer for Wordlist<String> {
+    type Buffer = String;
+    fn from_buffer(buffer: String) -> Result<Self, WormodError> {
+        let start = buffer.len() - buffer.trim_start().len();
+        let end = buffer.trim_end().len().max(start);
+        Wordlist::split(RawBuffer::from(buffer.into_bytes()), start, end)
+    }
+}
+
+impl FromBuffer for Wordlist<Vec<u8>> {
+    type Buffer = RawBuffer;
+    fn from_buffer(buffer: RawBuffer) -> Result<Self, WormodError> {
+        let start = buffer.len() - buffer.trim_ascii_start().len();
+        let end = buffer.trim_ascii_end().len().max(start);
+        Wordlist::split(buffer, start, end)
+    }
+}
+
+/// Fails if there is not enough memory left to index
+/// the given number of entries
+///
+/// The content of the entries is not copied, so only the index takes memory
+fn check_index_memory(n_entries: usize) -> Result<(), WormodError> {
+    let index_size = n_entries * std::mem::size_of::<Span>();
+    let available_memory = memory::available_memory();
+    if !memory::is_memory_enough_with(available_memory, index_size) {
+        return Err(error!(
+            Memory,
+            ("Not enough memory to complete the operation(s)")
+        ));
+    }
+    Ok(())
+}
+
+
+/// Removes duplicates from unsorted wordlists
+pub trait DedupUnsorted {
+    /// Removes duplicates while keeping the first occurrence of each entry
+    fn dedup_unsorted(&mut self, algorithm: HashAlgorithm) -> Result<(), WormodError>;
+    /// Removes duplicates while keeping the last occurrence of each entry
+    fn dedup_unsorted_keep_last(&mut self, algorithm: HashAlgorithm) -> Result<(), WormodError>;
 }
 
-impl FromBuffer for Vec<String> {
-    fn from_buffer(buffer: String) -> Self {
-        let entries = buffer.trim().split('\n').filter(|e| !e.is_empty());
-        let n_entries = entries.clone().count();
+impl<E: Entry> DedupUnsorted for Vec<E> {
+    fn dedup_unsorted(&mut self, algorithm: HashAlgorithm) -> Result<(), WormodError> {
+        let keep = first_occurrences_with(algorithm, self.iter().map(|e| e.as_bytes()))?;
+        retain_marked(self, &keep);
+        Ok(())
+    }
+
+    fn dedup_unsorted_keep_last(&mut self, algorithm: HashAlgorithm) -> Result<(), WormodError> {
+        let mut keep = first_occurrences_with(algorithm, self.iter().rev().map(|e| e.as_bytes()))?;
+        keep.reverse();
+        retain_marked(self, &keep);
+        Ok(())
+    }
+}
+
+impl<E: Entry> DedupUnsorted for Wordlist<E> {
+    fn dedup_unsorted(&mut self, algorithm: HashAlgorithm) -> Result<(), WormodError> {
+        let keep = first_occurrences_with(algorithm, self.iter())?;
+        self.retain_marked(&keep);
+        Ok(())
+    }
+
+    fn dedup_unsorted_keep_last(&mut self, algorithm: HashAlgorithm) -> Result<(), WormodError> {
+        let mut keep = first_occurrences_with(algorithm, self.iter().rev())?;
+        keep.reverse();
+        self.retain_marked(&keep);
+        Ok(())
+    }
+}
+
+/// Marks the entries which are seen for the first time,
+/// using the given hashing algorithm
+fn first_occurrences_with<'a>(algorithm: HashAlgorithm, entries: impl ExactSizeIterator<Item=&'a [u8]>) -> Result<Vec<bool>, WormodError> {
+    match algorithm {
+        HashAlgorithm::Siphash => first_occurrences::<RandomState>(entries),
+        HashAlgorithm::Fnv1a => first_occurrences::<BuildHasherDefault<Fnv1a>>(entries),
+    }
+}
+
+/// Marks the entries which are seen for the first time
+fn first_occurrences<'a, S: BuildHasher + Default>(entries: impl ExactSizeIterator<Item=&'a [u8]>) -> Result<Vec<bool>, WormodError> {
+    let n_entries = entries.len();
+    {
+        let set_size = n_entries * (std::mem::size_of::<&str>() + 1) * 2;
+        let available_memory = memory::available_memory();
+        if !memory::is_memory_enough_with(available_memory, set_size + n_entries) {
+            return Err(error!(
+                Memory,
+                ("Not enough memory to complete the operation(s)")
+            ));
+        }
+    }
+    let mut seen = HashSet::with_capacity_and_hasher(n_entries, S::default());
+    Ok(entries.map(|e| seen.insert(e)).collect())
+}
+
+/// Retains only the entries marked to be kept
+fn retain_marked<E>(wordlist: &mut Vec<E>, keep: &[bool]) {
+    let mut marks = keep.iter();
+    wordlist.retain(|_| *marks.next().unwrap());
+}
+
+
+/// Applies rules to whole wordlists
+pub trait ApplyRules: Sized {
+    /// Replaces each entry with the candidates resulting from the rules
+    fn apply_rules(self, rules: &[Rule], unit: LengthUnit) -> Result<Self, WormodError>;
+}
+
+impl<E: Entry> ApplyRules for Wordlist<E> {
+    /// Entries are split among the threads, and the candidates
+    /// of each thread are then joined in order
+    fn apply_rules(self, rules: &[Rule], unit: LengthUnit) -> Result<Self, WormodError> {
+        let n_threads = rayon::current_num_threads();
         {
-            let content_size = buffer.len() - n_entries;
-            let collection_size = n_entries * std::mem::size_of::<String>();
-            let wbuf_size = collection_size + content_size;
+            let content_size = self.content_size();
+            let collection_size = self.len() * std::mem::size_of::<Span>();
+            let mut wbuf_size = (collection_size + content_size).saturating_mul(rules.len());
+            if n_threads > 1 {
+                // the candidates are copied when joined
+                wbuf_size = wbuf_size.saturating_mul(2);
+            }
             let available_memory = memory::available_memory();
             if !memory::is_memory_enough_with(available_memory, wbuf_size) {
-                exit_err!(
-                    ("Not enough memory to complete the operation(s)")
-                );
+                return Err(error!(
+                    Memory,
+                    ("Not enough memory to apply the rules")
+                ));
             }
         }
-        entries.map(|e| e.to_owned()).collect()
+        let part_len = self.len().div_ceil(n_threads).max(1);
+        let parts : Vec<Wordlist<E>> = self.index.par_chunks(part_len)
+            .map(|spans| {
+                let mut candidates = Wordlist::new();
+                for s in spans {
+                    let entry = E::from_valid_bytes(self.buffer[s.range()].to_vec());
+                    rules.iter()
+                        .filter_map(|r| entry.apply_rule(r, unit))
+                        .for_each(|c| candidates.push(c.as_bytes()));
+                }
+                candidates
+            })
+            .collect();
+        let mut parts = parts.into_iter();
+        let mut candidates = parts.next().unwrap_or_default();
+        for part in parts {
+            part.iter().for_each(|c| candidates.push(c));
+        }
+        Ok(candidates)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub(super) trait DedupUnsorted {
-    fn dedup_unsorted(&mut self);
-}
+    const ENTRIES : [&str; 7] = ["b", "a", "c", "a", "b", "d", "a"];
 
-impl DedupUnsorted for Vec<String> {
-    fn dedup_unsorted(&mut self) {
-        let len = self.len();
-        let mut max = len;
-        let mut i = 0;
-        while i < max {
-            let mut j = i + 1;
-            while j < max {
-                if self[j] == self[i] {
-                    let mut t = j + 1;
-                    while t < max && self[t] == self[i] {
-                        t += 1;
-                    }
-                    let n_shifts = t - j;
-                    self[j..].rotate_left(n_shifts);
-                    max -= n_shifts;
-                    continue;
-                }
-                j += 1;
-            }
-            i += 1;
+    #[test]
+    fn dedup_unsorted_entries() {
+        for algorithm in [HashAlgorithm::Siphash, HashAlgorithm::Fnv1a] {
+            let mut entries : Vec<String> = ENTRIES.iter().map(|e| e.to_string()).collect();
+            entries.dedup_unsorted(algorithm).unwrap();
+            assert_eq!(entries, ["b", "a", "c", "d"]);
+            let mut entries : Vec<String> = ENTRIES.iter().map(|e| e.to_string()).collect();
+            entries.dedup_unsorted_keep_last(algorithm).unwrap();
+            assert_eq!(entries, ["c", "b", "d", "a"]);
+        }
+    }
+
+    #[test]
+    fn dedup_unsorted_wordlist() {
+        let wordlist = || {
+            let mut wordlist = Wordlist::<String>::new();
+            ENTRIES.iter().for_each(|e| wordlist.push(e.as_bytes()));
+            wordlist
+        };
+        for algorithm in [HashAlgorithm::Siphash, HashAlgorithm::Fnv1a] {
+            let mut unique = wordlist();
+            unique.dedup_unsorted(algorithm).unwrap();
+            assert_eq!(unique.entries().collect::<Vec<_>>(), ["b", "a", "c", "d"]);
+            let mut unique = wordlist();
+            unique.dedup_unsorted_keep_last(algorithm).unwrap();
+            assert_eq!(unique.entries().collect::<Vec<_>>(), ["c", "b", "d", "a"]);
+        }
+    }
+
+    #[test]
+    fn splits_single_buffer() {
+        let content : String = (0..10000).map(|i| format!("{}\n{}", i, "\n".repeat(i % 3))).collect();
+        let expected : Vec<String> = content.lines().filter(|l| !l.is_empty()).map(String::from).collect();
+        for n_threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(n_threads).build().unwrap();
+            let wordlist : Wordlist<String> = pool.install(|| Wordlist::from_buffer(format!("\n {}  ", content))).unwrap();
+            assert_eq!(wordlist.len(), expected.len());
+            assert_eq!(wordlist.entries().collect::<Vec<_>>(), expected);
         }
-        self.truncate(max);
+        let mut wordlist : Wordlist<Vec<u8>> = Wordlist::from_buffer(RawBuffer::from(b"ab\ncd\nef".to_vec())).unwrap();
+        wordlist.retain(|e| e != b"cd");
+        wordlist.push(b"gh");
+        assert_eq!(wordlist.iter().collect::<Vec<_>>(), [b"ab", b"ef", b"gh"]);
     }
 }
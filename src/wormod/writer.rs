@@ -1,85 +1,161 @@
+use super::compression;
+use super::compression::Encoder;
+use super::encoding::LineEncoder;
+use super::entry::Entry;
 use super::file;
+use super::hex;
 use super::memory;
+use super::wordlist::Wordlist;
+use crate::error::*;
+use crate::params::InvalidUtf8Policy;
 use crate::params::Params;
-use crate::print::*;
+use crate::params::TextEncoding;
 
 use std::io::BufWriter;
 use std::io::Write;
 
-pub(super) type Writer = BufWriter<Box<dyn std::io::Write>>;
+/// The output stream, along with how entries shall be written to it
+pub struct Writer {
+    stream: BufWriter<Encoder>,
+    /// Whether to encode entries in the `$HEX[...]` notation when needed
+    encode_hex: bool,
+    /// Whether entries can contain escaped raw bytes, to be restored
+    /// and encoded in the `$HEX[...]` notation, see [`hex::escape`]
+    unescape: bool,
+    /// Scratch buffer for the restored raw bytes
+    raw: Vec<u8>,
+    /// Scratch buffer for encoded entries
+    encoded: String,
+    /// The encoder of the output, `None` when writing UTF-8
+    encoder: Option<LineEncoder>,
+}
+
+impl Writer {
+    /// Writes the entry followed by a line feed, encoding it if needed
+    ///
+    /// Entries not representable in the output encoding may be skipped.
+    /// Write failures are described by `failure`
+    fn write_line(&mut self, entry: &[u8], failure: &str) -> Result<(), WormodError> {
+        let escaped = self.unescape && std::str::from_utf8(entry).is_ok_and(|e| {
+            self.raw.clear();
+            hex::unescape(e, &mut self.raw)
+        });
+        let entry = if escaped {
+            self.encoded.clear();
+            hex::encode(&self.raw, &mut self.encoded);
+            self.encoded.as_bytes()
+        } else if self.encode_hex && hex::needs_encoding(entry) {
+            self.encoded.clear();
+            hex::encode(entry, &mut self.encoded);
+            self.encoded.as_bytes()
+        } else {
+            entry
+        };
+        let written = match self.encoder.as_mut() {
+            None => self.stream.write_all(entry).and_then(|_| self.stream.write_all(&LF)),
+            Some(encoder) => match encoder.encode_line(entry)? {
+                Some(line) => self.stream.write_all(line),
+                None => Ok(()),
+            },
+        };
+        written.map_err(|e| error!(
+            Io,
+            ("{}: {}", failure, e.to_string())
+        ))
+    }
+
+    /// Reports statistics about the entries written
+    pub fn report(&self) {
+        if let Some(encoder) = self.encoder.as_ref() {
+            encoder.report();
+        }
+    }
+}
 
 /// Line feed
 const LF : [u8;1] = [0xA];
 
-pub(super) fn buffered_writer(params: &Params) -> Writer {
+/// Returns the writer of the output wordlist
+///
+/// The output is compressed and encoded as requested
+pub fn buffered_writer(params: &Params) -> Result<Writer, WormodError> {
     let available_memory = memory::available_memory();
-    let buffer_size = memory::buffer_size(available_memory);
-    let buf_writer : Writer;
+    let buffer_size = memory::buffer_size(available_memory)?;
+    let buf_writer : BufWriter<Encoder>;
 
     if let Some(out_path) = params.output.as_ref() {
-        params.check_output_path();
-        let out_file = file::open_output_file(out_path, params.append_output);
-        buf_writer = BufWriter::with_capacity(buffer_size, Box::new(out_file));
+        params.check_output_path()?;
+        let out_file = file::open_output_file(out_path, params.append_output)?;
+        let out_stream = compression::encoder(Box::new(out_file), params.output_compression())?;
+        buf_writer = BufWriter::with_capacity(buffer_size, out_stream);
     } else {
         // writing to standard output
-        buf_writer = BufWriter::with_capacity(buffer_size, Box::new(std::io::stdout()));
+        let out_stream = compression::encoder(Box::new(std::io::stdout()), params.output_compression())?;
+        buf_writer = BufWriter::with_capacity(buffer_size, out_stream);
     }
 
-    buf_writer
+    Ok(Writer {
+        stream: buf_writer,
+        encode_hex: params.encode_hex,
+        unescape: matches!(params.invalid_utf8, InvalidUtf8Policy::Hex) && !params.bytes,
+        raw: Vec::new(),
+        encoded: String::new(),
+        encoder: (params.output_encoding != TextEncoding::Utf8)
+            .then(|| LineEncoder::new(params.output_encoding, params.unrepresentable)),
+    })
 }
 
-pub(super) fn write_to_file(mut writer: Writer, wordlist: Vec<String>) {
+/// Writes the whole wordlist to the output file
+pub fn write_to_file<E: Entry>(writer: &mut Writer, wordlist: Wordlist<E>) -> Result<(), WormodError> {
     for buf in wordlist.iter().filter(|s| !s.is_empty()) {
-        if let Err(e) = writer.write_all(buf.as_bytes()) {
-            exit_err!(
-                ("Failed to entirely write output file: {}", e.to_string())
-            );
-        }
-        if let Err(e) = writer.write(&LF) {
-            match e.kind() {
-                std::io::ErrorKind::Interrupted => continue,
-                _ => {
-                    exit_err!(
-                        ("Failed to write: {}", e.to_string())
-                    );
-                }
-            }
-        }
+        writer.write_line(buf, "Failed to entirely write output file")?;
     }
+    Ok(())
 }
 
-pub(super) fn write_to_stdout(mut writer: Writer, wordlist: Vec<String>) {
+/// Writes the whole wordlist to the standard output
+pub fn write_to_stdout<E: Entry>(writer: &mut Writer, wordlist: Wordlist<E>) -> Result<(), WormodError> {
     for buf in wordlist.iter().filter(|s| !s.is_empty()) {
-        if let Err(e) = writer.write_all(buf.as_bytes()) {
-            exit_err!(
-                ("Failed to entirely write to standard output: {}", e.to_string())
-            );
-        }
-        if let Err(e) = writer.write(&LF) {
-            match e.kind() {
-                std::io::ErrorKind::Interrupted => continue,
-                _ => {
-                    exit_err!(
-                        ("Failed to write: {}", e.to_string())
-                    );
-                }
-            }
-        }
+        writer.write_line(buf, "Failed to entirely write to standard output")?;
     }
+    Ok(())
 }
 
-pub(super) fn pipe_write(writer: &mut Writer, buffer: &String) {
-    if let Err(e) = writer.write_all(buffer.as_bytes()) {
-        exit_err!(
-            ("Failed to write: {}", e.to_string())
-        );
-    } else if let Err(e) = writer.write_all(&LF) {
-        exit_err!(
+/// Writes one entry and flushes
+pub fn pipe_write(writer: &mut Writer, buffer: &[u8]) -> Result<(), WormodError> {
+    writer.write_line(buffer, "Failed to write")?;
+    flush(writer)
+}
+
+/// Flushes the entries written so far
+pub fn flush(writer: &mut Writer) -> Result<(), WormodError> {
+    if let Err(e) = writer.stream.flush() {
+        return Err(error!(
+            Io,
             ("Failed to write: {}", e.to_string())
-        );
-    } else if let Err(e) = writer.flush() {
-        exit_err!(
+        ));
+    }
+    Ok(())
+}
+
+/// Writes one entry without flushing
+pub fn write_entry(writer: &mut Writer, buffer: &[u8]) -> Result<(), WormodError> {
+    writer.write_line(buffer, "Failed to write")
+}
+
+/// Flushes the remaining entries and completes the compressed stream
+///
+/// Must be called once all the entries have been written, since
+/// failures cannot be reported when the writer is merely dropped
+pub fn finish(writer: Writer) -> Result<(), WormodError> {
+    let finished = writer.stream.into_inner()
+        .map_err(|e| e.into_error())
+        .and_then(|encoder| encoder.finish());
+    if let Err(e) = finished {
+        return Err(error!(
+            Io,
             ("Failed to write: {}", e.to_string())
-        );
+        ));
     }
+    Ok(())
 }